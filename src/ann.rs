@@ -0,0 +1,229 @@
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::{HashMap, HashSet};
+
+// Approximate nearest neighbour search over normalized vectors, by random hyperplane
+// locality-sensitive hashing. Each table hashes a vector to the side of BITS random
+// hyperplanes it is on; similar vectors tend to land in the same bucket. A search scores
+// the vectors in the query's buckets, and the buckets one bit away, in every table.
+// Small indexes are searched exhaustively, which is exact and still fast.
+const TABLES: usize = 8;
+const BITS: usize = 12;
+const EXACT_LIMIT: usize = 20_000;
+// The hyperplanes only depend on the number of dimensions, so buckets are the same in
+// every run
+const SEED: u64 = 0x706d73;
+
+pub struct AnnIndex {
+    dimensions: usize,
+    // TABLES * BITS hyperplanes, by their normal vector
+    planes: Vec<Vec<f32>>,
+    // Bucket key to positions in ids/vectors, per table
+    tables: Vec<HashMap<u32, Vec<usize>>>,
+    ids: Vec<u64>,
+    vectors: Vec<Vec<f32>>,
+    positions: HashMap<u64, usize>,
+}
+
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    a.iter().zip(b).map(|(x, y)| x * y).sum()
+}
+
+impl AnnIndex {
+    pub fn new(dimensions: usize) -> Self {
+        let mut rng = StdRng::seed_from_u64(SEED);
+        // Normally distributed components (Box-Muller), so the planes are uniformly
+        // oriented
+        let mut gaussian = move || {
+            let u1: f32 = rng.gen_range(f32::EPSILON..1.0);
+            let u2: f32 = rng.gen();
+            (-2.0 * u1.ln()).sqrt() * (std::f32::consts::TAU * u2).cos()
+        };
+        let planes = (0..TABLES * BITS)
+            .map(|_| (0..dimensions).map(|_| gaussian()).collect())
+            .collect();
+        AnnIndex {
+            dimensions,
+            planes,
+            tables: vec![HashMap::new(); TABLES],
+            ids: vec![],
+            vectors: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.ids.is_empty()
+    }
+
+    pub fn contains(&self, id: u64) -> bool {
+        self.positions.contains_key(&id)
+    }
+
+    pub fn ids(&self) -> impl Iterator<Item = u64> + '_ {
+        self.ids.iter().copied()
+    }
+
+    pub fn vector(&self, id: u64) -> Option<&[f32]> {
+        self.positions
+            .get(&id)
+            .map(|&position| self.vectors[position].as_slice())
+    }
+
+    fn key(&self, table: usize, vector: &[f32]) -> u32 {
+        self.planes[table * BITS..(table + 1) * BITS]
+            .iter()
+            .enumerate()
+            .fold(0, |key, (bit, plane)| {
+                if dot(plane, vector) >= 0.0 {
+                    key | 1 << bit
+                } else {
+                    key
+                }
+            })
+    }
+
+    // Add a vector, replacing the one of the same id
+    pub fn insert(&mut self, id: u64, vector: Vec<f32>) {
+        assert_eq!(vector.len(), self.dimensions);
+        self.remove(id);
+        let position = self.ids.len();
+        for table in 0..TABLES {
+            let key = self.key(table, &vector);
+            self.tables[table].entry(key).or_default().push(position);
+        }
+        self.ids.push(id);
+        self.vectors.push(vector);
+        self.positions.insert(id, position);
+    }
+
+    pub fn remove(&mut self, id: u64) {
+        let position = match self.positions.remove(&id) {
+            Some(position) => position,
+            None => return,
+        };
+        // Move the last vector into the freed position
+        let last = self.ids.len() - 1;
+        for table in 0..TABLES {
+            let key = self.key(table, &self.vectors[position]);
+            let last_key = self.key(table, &self.vectors[last]);
+            let bucket = self.tables[table].get_mut(&key).unwrap();
+            bucket.retain(|&p| p != position);
+            if bucket.is_empty() {
+                self.tables[table].remove(&key);
+            }
+            if position != last {
+                for p in self.tables[table].get_mut(&last_key).unwrap() {
+                    if *p == last {
+                        *p = position;
+                    }
+                }
+            }
+        }
+        self.ids.swap_remove(position);
+        self.vectors.swap_remove(position);
+        if position != last {
+            self.positions.insert(self.ids[position], position);
+        }
+    }
+
+    // The ids of the vectors most similar to the query, most similar first, with their
+    // cosine similarity
+    pub fn search(&self, query: &[f32], limit: usize) -> Vec<(u64, f32)> {
+        let candidates: Vec<usize> = if self.len() <= EXACT_LIMIT {
+            (0..self.len()).collect()
+        } else {
+            let mut candidates = HashSet::new();
+            for table in 0..TABLES {
+                let key = self.key(table, query);
+                let probes = std::iter::once(key).chain((0..BITS).map(|bit| key ^ 1 << bit));
+                for probe in probes {
+                    if let Some(bucket) = self.tables[table].get(&probe) {
+                        candidates.extend(bucket);
+                    }
+                }
+            }
+            candidates.into_iter().collect()
+        };
+        let mut hits: Vec<(u64, f32)> = candidates
+            .into_iter()
+            .map(|position| (self.ids[position], dot(query, &self.vectors[position])))
+            .collect();
+        hits.sort_by(|a, b| b.1.total_cmp(&a.1));
+        hits.truncate(limit);
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vector(rng: &mut StdRng, dimensions: usize) -> Vec<f32> {
+        let vector: Vec<f32> = (0..dimensions).map(|_| rng.gen_range(-1.0..1.0)).collect();
+        let norm = dot(&vector, &vector).sqrt();
+        vector.into_iter().map(|x| x / norm).collect()
+    }
+
+    // Every vector is in its bucket of every table, and the buckets hold nothing else
+    fn assert_consistent(index: &AnnIndex) {
+        for table in 0..TABLES {
+            let positions: usize = index.tables[table].values().map(Vec::len).sum();
+            assert_eq!(positions, index.len());
+            for (position, vector) in index.vectors.iter().enumerate() {
+                let key = index.key(table, vector);
+                assert!(index.tables[table][&key].contains(&position));
+            }
+        }
+        for (position, id) in index.ids.iter().enumerate() {
+            assert_eq!(index.positions[id], position);
+        }
+    }
+
+    #[test]
+    fn insert_and_remove() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut index = AnnIndex::new(16);
+        let vectors: Vec<Vec<f32>> = (0..50).map(|_| vector(&mut rng, 16)).collect();
+        for (id, vector) in vectors.iter().enumerate() {
+            index.insert(id as u64, vector.clone());
+        }
+        assert_consistent(&index);
+
+        // Including the first and the last position
+        for id in [0, 49, 10, 11, 30, 1000] {
+            index.remove(id);
+        }
+        assert_eq!(index.len(), 45);
+        assert!(!index.contains(10));
+        assert!(index.vector(30).is_none());
+        assert_consistent(&index);
+
+        // Replacing a vector keeps one entry for the id
+        index.insert(5, vectors[6].clone());
+        assert_eq!(index.len(), 45);
+        assert_eq!(index.vector(5), Some(vectors[6].as_slice()));
+        assert_consistent(&index);
+    }
+
+    #[test]
+    fn search_finds_closest() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let mut index = AnnIndex::new(16);
+        let vectors: Vec<Vec<f32>> = (0..20).map(|_| vector(&mut rng, 16)).collect();
+        for (id, vector) in vectors.iter().enumerate() {
+            index.insert(id as u64, vector.clone());
+        }
+        index.remove(3);
+        let results = index.search(&vectors[7], 5);
+        assert_eq!(results.len(), 5);
+        assert_eq!(results[0].0, 7);
+        assert!((results[0].1 - 1.0).abs() < 1e-4);
+        assert!(results.windows(2).all(|pair| pair[0].1 >= pair[1].1));
+        assert!(index.search(&vectors[3], 20).iter().all(|(id, _)| *id != 3));
+    }
+}
@@ -0,0 +1,369 @@
+use indicatif::ProgressBar;
+use prost::Message;
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fs::{create_dir_all, File};
+use std::io::{BufRead, BufReader, Read};
+use std::path::{Path, PathBuf};
+use tantivy::collector::TopDocs;
+use tantivy::query::TermQuery;
+use tantivy::schema::{IndexRecordOption, Schema, Term};
+use tantivy::Searcher;
+use tracing::{info, warn};
+
+use crate::api::{OcrWords, ScreenRecord, WordBox};
+use crate::config::Config;
+use crate::dhash::{image_dhash, packed_dhash_from_hex, packed_dhash_to_hex};
+use crate::index::{indexed_ids, make_schema, record_document, stored_dhash};
+use crate::storage::{
+    datetime_to_screen_path, read_file, read_sidecar, screen_uid, stored_screens, to_timestamp,
+    write_new_file, write_sidecar,
+};
+
+// Portable archives of a time range, for moving screens between machines. An archive is
+// an uncompressed tar file with:
+//
+// images/...      the images as plain JPEGs (decrypted), named by ScreenEntry.image
+// manifest.json   format name and version, creation time, time range and screen count
+// screens.jsonl   one ScreenEntry per line, oldest first
+//
+// The images come first, so export reads each of them only once. Archives written with
+// the metadata first are read the same way.
+const FORMAT: &str = "pms-archive";
+const FORMAT_VERSION: u32 = 1;
+const MANIFEST_PATH: &str = "manifest.json";
+const SCREENS_PATH: &str = "screens.jsonl";
+// Times are UTC, with nanoseconds
+const TIME_FORMAT: &str = "%Y-%m-%dT%H:%M:%S%.9fZ";
+
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    format: String,
+    version: u32,
+    created: String,
+    start: String,
+    end: String,
+    screens: usize,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ScreenEntry {
+    id: u64,
+    time: String,
+    screen_id: u32,
+    // Path of the image in the archive
+    image: String,
+    // Difference hash of the image, as hex
+    dhash: String,
+    text: String,
+    words: Vec<WordEntry>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct WordEntry {
+    text: String,
+    left: u32,
+    top: u32,
+    width: u32,
+    height: u32,
+    confidence: f32,
+}
+
+impl From<WordBox> for WordEntry {
+    fn from(word: WordBox) -> Self {
+        WordEntry {
+            text: word.text,
+            left: word.left,
+            top: word.top,
+            width: word.width,
+            height: word.height,
+            confidence: word.confidence,
+        }
+    }
+}
+
+impl From<WordEntry> for WordBox {
+    fn from(word: WordEntry) -> Self {
+        WordBox {
+            text: word.text,
+            left: word.left,
+            top: word.top,
+            width: word.width,
+            height: word.height,
+            confidence: word.confidence,
+        }
+    }
+}
+
+fn format_time(datetime: chrono::NaiveDateTime) -> String {
+    datetime.format(TIME_FORMAT).to_string()
+}
+
+fn parse_time(time: &str) -> Result<chrono::NaiveDateTime, chrono::ParseError> {
+    chrono::NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M:%S%.fZ")
+}
+
+// Parse a command line time: a day (YYYY-MM-DD) or a time (YYYY-MM-DDTHH:MM:SS), in UTC.
+// A day as the end of a range includes the whole day.
+pub fn parse_range_bound(value: &str, is_end: bool) -> Result<chrono::NaiveDateTime, String> {
+    if let Ok(datetime) = chrono::NaiveDateTime::parse_from_str(value, "%Y-%m-%dT%H:%M:%S") {
+        return Ok(datetime);
+    }
+    let day = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+        .map_err(|_| format!("Invalid time {:?}, expected YYYY-MM-DD[THH:MM:SS]", value))?;
+    let day = if is_end { day.succ_opt().unwrap() } else { day };
+    Ok(day.and_hms_opt(0, 0, 0).unwrap())
+}
+
+// OCR output of a screen from the index, for screens stored before sidecars were written
+fn indexed_record(searcher: &Searcher, schema: &Schema, id: u64) -> Option<(String, Vec<WordBox>)> {
+    let query = TermQuery::new(
+        Term::from_field_u64(schema.get_field("id").unwrap(), id),
+        IndexRecordOption::Basic,
+    );
+    let (_score, address) = *searcher
+        .search(&query, &TopDocs::with_limit(1))
+        .ok()?
+        .first()?;
+    let doc = searcher.doc(address).ok()?;
+    let text = doc
+        .get_first(schema.get_field("text").unwrap())
+        .and_then(|value| value.as_text())
+        .unwrap_or("")
+        .to_string();
+    let words = doc
+        .get_first(schema.get_field("words").unwrap())
+        .and_then(|value| value.as_bytes())
+        .and_then(|bytes| OcrWords::decode(bytes).ok())
+        .map(|words| words.words)
+        .unwrap_or_default();
+    Some((text, words))
+}
+
+// Export the screens captured in [start, end) to an archive
+pub fn export(
+    start: chrono::NaiveDateTime,
+    end: chrono::NaiveDateTime,
+    path: &Path,
+) -> Result<usize, Box<dyn Error>> {
+    let mut screens: Vec<_> = stored_screens()
+        .into_iter()
+        .filter(|screen| screen.datetime >= start && screen.datetime < end)
+        .collect();
+    screens.sort_by_key(|screen| screen.datetime);
+    info!(screens = screens.len(), "Exporting screens");
+
+    let (schema, index) = make_schema();
+    let searcher = index.reader()?.searcher();
+    let mut builder = tar::Builder::new(File::create(path)?);
+    let mut entries = vec![];
+    let pb = ProgressBar::new(screens.len() as u64);
+    for screen in &screens {
+        let image = read_file(&screen.path)?;
+        let sidecar = read_sidecar(&screen.path);
+        let dhash = match &sidecar {
+            Some(record) if !record.dhash.is_empty() => record.dhash.clone(),
+            // Stored before image hashes were kept
+            _ => image_dhash(&image)
+                .ok_or_else(|| format!("{}: invalid image", screen.path.display()))?,
+        };
+        let (text, words) = match sidecar {
+            Some(record) => (record.text, record.words),
+            None => indexed_record(&searcher, &schema, screen.id).unwrap_or_default(),
+        };
+        let (dir, fname) = datetime_to_screen_path(screen.datetime, screen.screen_id);
+        let entry = ScreenEntry {
+            id: screen.id,
+            time: format_time(screen.datetime),
+            screen_id: screen.screen_id,
+            image: format!("images/{}{}", dir.trim_start_matches("screenshots/"), fname),
+            dhash: packed_dhash_to_hex(&dhash),
+            text,
+            words: words.into_iter().map(WordEntry::from).collect(),
+        };
+        append_file(&mut builder, &entry.image, &image)?;
+        entries.push(entry);
+        pb.inc(1);
+    }
+
+    let manifest = Manifest {
+        format: FORMAT.to_string(),
+        version: FORMAT_VERSION,
+        created: format_time(chrono::Utc::now().naive_utc()),
+        start: format_time(start),
+        end: format_time(end),
+        screens: entries.len(),
+    };
+    let mut lines = vec![];
+    for entry in &entries {
+        serde_json::to_writer(&mut lines, entry)?;
+        lines.push(b'\n');
+    }
+
+    append_file(
+        &mut builder,
+        MANIFEST_PATH,
+        &serde_json::to_vec_pretty(&manifest)?,
+    )?;
+    append_file(&mut builder, SCREENS_PATH, &lines)?;
+    builder.into_inner()?.sync_all()?;
+    pb.finish();
+    Ok(entries.len())
+}
+
+fn append_file(builder: &mut tar::Builder<File>, path: &str, data: &[u8]) -> std::io::Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(chrono::Utc::now().timestamp() as u64);
+    builder.append_data(&mut header, path, data)
+}
+
+// Read the manifest and screen list of an archive
+fn read_metadata(path: &Path) -> Result<(Manifest, Vec<ScreenEntry>), Box<dyn Error>> {
+    let mut manifest = None;
+    let mut entries = vec![];
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for file in archive.entries()? {
+        let mut file = file?;
+        let name = file.path()?.to_string_lossy().to_string();
+        if name == MANIFEST_PATH {
+            let mut data = vec![];
+            file.read_to_end(&mut data)?;
+            manifest = Some(serde_json::from_slice::<Manifest>(&data)?);
+        } else if name == SCREENS_PATH {
+            for line in BufReader::new(file).lines() {
+                let line = line?;
+                if !line.trim().is_empty() {
+                    entries.push(serde_json::from_str(&line)?);
+                }
+            }
+        }
+    }
+    let manifest = manifest.ok_or("Not a PMS archive: no manifest.json")?;
+    if manifest.format != FORMAT {
+        return Err(format!("Not a PMS archive: format {:?}", manifest.format).into());
+    }
+    if manifest.version > FORMAT_VERSION {
+        return Err(format!(
+            "Archive version {} is newer than this pms-server supports ({})",
+            manifest.version, FORMAT_VERSION
+        )
+        .into());
+    }
+    Ok((manifest, entries))
+}
+
+// Merge an archive into this server's screenshots and index. Screens whose id is already
+// stored are skipped, as are screens with the same dhash as a stored screen from the same
+// time range, or as another screen in the archive. The server must not be running.
+// Returns the number of imported and skipped screens.
+pub fn import(config: &Config, path: &Path) -> Result<(usize, usize), Box<dyn Error>> {
+    let (manifest, entries) = read_metadata(path)?;
+    info!(
+        screens = entries.len(),
+        start = %manifest.start,
+        end = %manifest.end,
+        "Importing archive"
+    );
+
+    let (schema, index) = make_schema();
+    let mut known_ids = indexed_ids(&index, &schema);
+    let stored = stored_screens();
+    known_ids.extend(stored.iter().map(|screen| screen.id));
+    // Hashing every stored image would take long, so only the screens in the archive's
+    // time range are compared by hash
+    let range_start = parse_time(&manifest.start)?;
+    let range_end = parse_time(&manifest.end)?;
+    let mut known_hashes = HashSet::new();
+    for screen in stored
+        .iter()
+        .filter(|screen| screen.datetime >= range_start && screen.datetime < range_end)
+    {
+        let dhash = match read_sidecar(&screen.path) {
+            Some(record) if !record.dhash.is_empty() => record.dhash,
+            _ => stored_dhash(&screen.path),
+        };
+        if dhash.is_empty() {
+            warn!(path = %screen.path.display(), "Could not read image");
+        } else {
+            known_hashes.insert(packed_dhash_to_hex(&dhash));
+        }
+    }
+
+    let mut skipped = 0;
+    let mut accepted: HashMap<String, ScreenEntry> = HashMap::new();
+    for entry in entries {
+        // The screen is stored under the id of its time and screen, whatever id the
+        // archive gives it
+        let id = screen_uid(parse_time(&entry.time)?, entry.screen_id);
+        if known_ids.contains(&id) || !known_hashes.insert(entry.dhash.clone()) {
+            skipped += 1;
+            continue;
+        }
+        known_ids.insert(id);
+        accepted.insert(entry.image.clone(), entry);
+    }
+
+    let mut writer = index.writer(50_000_000).map_err(|e| {
+        format!(
+            "Could not open the index for writing, is the server running? ({})",
+            e
+        )
+    })?;
+    let pb = ProgressBar::new(accepted.len() as u64);
+    let mut count = 0;
+    let mut archive = tar::Archive::new(File::open(path)?);
+    for file in archive.entries()? {
+        let mut file = file?;
+        let name = file.path()?.to_string_lossy().to_string();
+        let entry = match accepted.remove(&name) {
+            Some(entry) => entry,
+            None => continue,
+        };
+        let mut image = vec![];
+        file.read_to_end(&mut image)?;
+
+        let datetime = parse_time(&entry.time)?;
+        let (dir, fname) = datetime_to_screen_path(datetime, entry.screen_id);
+        create_dir_all(&dir)?;
+        let image_path = PathBuf::from(dir + &fname);
+        match write_new_file(&image_path, &image) {
+            Ok(()) => {}
+            // Stored since the screens on disk were listed
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                warn!(path = %image_path.display(), "Skipping a screen that is already stored");
+                skipped += 1;
+                pb.inc(1);
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        }
+        let record = ScreenRecord {
+            time: Some(to_timestamp(datetime)),
+            screen_id: entry.screen_id,
+            text: entry.text,
+            words: entry.words.into_iter().map(WordBox::from).collect(),
+            dhash: packed_dhash_from_hex(&entry.dhash)
+                .or_else(|| image_dhash(&image))
+                .unwrap_or_default(),
+        };
+        write_sidecar(&image_path, &record)?;
+        writer.add_document(record_document(config, &schema, record))?;
+        count += 1;
+        if count % config.reindex_checkpoint == 0 {
+            writer.commit()?;
+        }
+        pb.inc(1);
+    }
+    writer.commit()?;
+    pb.finish();
+    if !accepted.is_empty() {
+        warn!(
+            missing = accepted.len(),
+            "Images listed in the archive are missing"
+        );
+    }
+    Ok((count, skipped))
+}
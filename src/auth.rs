@@ -0,0 +1,197 @@
+use tonic::{Request, Status};
+
+use crate::config::Config;
+
+// What a client may do. Upload-only clients (pms-client) get Upload, the web interface
+// and other search clients get Search. Deleting screens is a separate scope, so the
+// search token, which the web interface stores in the browser, can't destroy data.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scope {
+    Upload,
+    Search,
+    Delete,
+}
+
+// The scopes granted to a request, put in its extensions by the interceptor
+#[derive(Clone, Copy, Debug)]
+pub struct Granted {
+    upload: bool,
+    search: bool,
+    delete: bool,
+}
+
+impl Granted {
+    // Every scope, for trusted in-process callers
+    pub const ALL: Granted = Granted {
+        upload: true,
+        search: true,
+        delete: true,
+    };
+
+    // A request with these scopes, for calling an RPC method directly
+    pub fn request<T>(self, message: T) -> Request<T> {
+        let mut request = Request::new(message);
+        request.extensions_mut().insert(self);
+        request
+    }
+}
+
+// Interceptor checking the bearer token in the "authorization" metadata. An upload or
+// search scope whose token is not configured is open to everyone. Deleting is only open
+// if no token is configured at all.
+pub fn interceptor(
+    config: &Config,
+) -> impl FnMut(Request<()>) -> Result<Request<()>, Status> + Clone {
+    let config = config.clone();
+    move |mut request: Request<()>| {
+        let header = match request.metadata().get("authorization") {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .map_err(|_| Status::unauthenticated("Invalid authorization header"))?,
+            ),
+            None => None,
+        };
+        let granted = grant(&config, header)?;
+        request.extensions_mut().insert(granted);
+        Ok(request)
+    }
+}
+
+// The scopes granted by an authorization header ("Bearer <token>")
+fn grant(config: &Config, header: Option<&str>) -> Result<Granted, Status> {
+    let token = match header {
+        Some(header) => Some(
+            header
+                .strip_prefix("Bearer ")
+                .ok_or_else(|| Status::unauthenticated("Invalid authorization header"))?,
+        ),
+        None => None,
+    };
+    let grants = |expected: &Option<String>| match (expected, token) {
+        (None, _) => true,
+        (Some(expected), Some(token)) => tokens_equal(expected, token),
+        (Some(_), None) => false,
+    };
+    let no_tokens = config.upload_token.is_none()
+        && config.search_token.is_none()
+        && config.delete_token.is_none();
+    let granted = Granted {
+        upload: grants(&config.upload_token),
+        search: grants(&config.search_token),
+        delete: no_tokens || (config.delete_token.is_some() && grants(&config.delete_token)),
+    };
+    if !granted.upload && !granted.search && !granted.delete {
+        return Err(Status::unauthenticated("Missing or invalid token"));
+    }
+    Ok(granted)
+}
+
+// Check an authorization header for a request that does not go through the gRPC
+// interceptor, e.g. the JSON API. Returns the grant to call the RPC methods with.
+pub fn authorize_header(
+    config: &Config,
+    header: Option<&str>,
+    scope: Scope,
+) -> Result<Granted, Status> {
+    let granted = grant(config, header)?;
+    authorize(&granted.request(()), scope)?;
+    Ok(granted)
+}
+
+// Check that the request's token grants the scope needed for an RPC. Requests that did
+// not go through the interceptor have no grant and are denied.
+pub fn authorize<T>(request: &Request<T>, scope: Scope) -> Result<(), Status> {
+    let allowed = match request.extensions().get::<Granted>() {
+        Some(granted) => match scope {
+            Scope::Upload => granted.upload,
+            Scope::Search => granted.search,
+            Scope::Delete => granted.delete,
+        },
+        None => false,
+    };
+    if allowed {
+        Ok(())
+    } else {
+        Err(Status::permission_denied(format!(
+            "Token does not allow {:?}",
+            scope
+        )))
+    }
+}
+
+// Compare in constant time, so the token cannot be guessed byte by byte from timings
+fn tokens_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (x, y)| diff | (x ^ y))
+            == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(upload: Option<&str>, search: Option<&str>, delete: Option<&str>) -> Config {
+        Config {
+            upload_token: upload.map(String::from),
+            search_token: search.map(String::from),
+            delete_token: delete.map(String::from),
+            ..Config::from_env()
+        }
+    }
+
+    fn scopes(config: &Config, header: Option<&str>) -> Option<(bool, bool, bool)> {
+        grant(config, header)
+            .ok()
+            .map(|granted| (granted.upload, granted.search, granted.delete))
+    }
+
+    #[test]
+    fn no_tokens_grant_everything() {
+        let config = config(None, None, None);
+        assert_eq!(scopes(&config, None), Some((true, true, true)));
+        assert_eq!(scopes(&config, Some("Bearer x")), Some((true, true, true)));
+    }
+
+    #[test]
+    fn tokens_grant_their_scope() {
+        let config = config(Some("up"), Some("find"), Some("del"));
+        assert_eq!(
+            scopes(&config, Some("Bearer up")),
+            Some((true, false, false))
+        );
+        assert_eq!(
+            scopes(&config, Some("Bearer find")),
+            Some((false, true, false))
+        );
+        assert_eq!(
+            scopes(&config, Some("Bearer del")),
+            Some((false, false, true))
+        );
+        assert_eq!(scopes(&config, Some("Bearer other")), None);
+        assert_eq!(scopes(&config, Some("up")), None);
+        assert_eq!(scopes(&config, None), None);
+    }
+
+    #[test]
+    fn delete_needs_its_own_token() {
+        // Search is open to everyone, but deleting is not
+        let config = config(Some("up"), None, None);
+        assert_eq!(scopes(&config, None), Some((false, true, false)));
+        assert_eq!(
+            scopes(&config, Some("Bearer up")),
+            Some((true, true, false))
+        );
+    }
+
+    #[test]
+    fn requests_without_grant_are_denied() {
+        assert!(authorize(&Request::new(()), Scope::Search).is_err());
+        assert!(authorize(&Granted::ALL.request(()), Scope::Delete).is_ok());
+        let config = config(None, Some("find"), None);
+        assert!(authorize_header(&config, Some("Bearer find"), Scope::Search).is_ok());
+        assert!(authorize_header(&config, Some("Bearer find"), Scope::Delete).is_err());
+    }
+}
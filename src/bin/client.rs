@@ -1,18 +1,244 @@
 use pms::api::pms_service_client::PmsServiceClient;
-use pms::api::UploadScreenRequest;
+use pms::api::search_request::Mode;
+use pms::api::{SearchRequest, UploadScreenRequest};
 
 use futures::future;
 use futures_util::stream;
 use image::{codecs::jpeg::JpegEncoder, DynamicImage, ImageBuffer};
+use pms::archive::parse_range_bound;
+use pms::logging;
 use pms::screenshot::{all_screens, capture_screen};
+use pms::storage::{from_timestamp, to_timestamp};
 use rgb::*;
+use std::path::PathBuf;
 use std::time::{Duration, SystemTime};
 use tokio::time;
+use tonic::codegen::InterceptedService;
+use tonic::metadata::{Ascii, MetadataValue};
+use tonic::service::Interceptor;
+use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::{Request, Status};
+use tracing::{error, info_span, warn, Instrument};
+
+type Client = PmsServiceClient<InterceptedService<Channel, Authorization>>;
+
+// Adds the bearer token, if any, to every request
+#[derive(Clone)]
+struct Authorization(Option<MetadataValue<Ascii>>);
+
+impl Interceptor for Authorization {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        if let Some(authorization) = &self.0 {
+            request
+                .metadata_mut()
+                .insert("authorization", authorization.clone());
+        }
+        Ok(request)
+    }
+}
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let mut client = PmsServiceClient::connect("http://[::1]:50001").await?;
+    logging::init();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        None => capture(connect("PMS_UPLOAD_TOKEN").await?).await,
+        Some("search") => match SearchOptions::parse(&args[2..]) {
+            Ok(options) => search(connect("PMS_SEARCH_TOKEN").await?, options).await,
+            Err(e) => {
+                eprintln!("{}", e);
+                eprintln!(
+                    "Usage: pms-client search <query> [--since <time>] [--until <time>] [--json] \
+                     [--save <dir> [--open]] [--mode keyword|semantic|hybrid]"
+                );
+                std::process::exit(2);
+            }
+        },
+        _ => {
+            eprintln!("Usage: pms-client [search <query> [options]]");
+            std::process::exit(2);
+        }
+    }
+}
+
+// Connect to the server, authenticating with the token in the given variable
+async fn connect(token_var: &str) -> Result<Client, Box<dyn std::error::Error>> {
+    // PMS_SERVER is the server URL. For a server with TLS, use an https:// URL and set
+    // PMS_TLS_CA to the CA certificate that signed the server's certificate.
+    let server = std::env::var("PMS_SERVER").unwrap_or("http://[::1]:50001".to_string());
+    let mut endpoint = Channel::from_shared(server)?;
+    if let Ok(ca) = std::env::var("PMS_TLS_CA") {
+        let ca = Certificate::from_pem(std::fs::read(ca)?);
+        endpoint = endpoint.tls_config(ClientTlsConfig::new().ca_certificate(ca))?;
+    }
+    let channel = endpoint.connect().await?;
+    let authorization = match std::env::var(token_var) {
+        Ok(token) => Some(format!("Bearer {}", token).parse()?),
+        Err(_) => None,
+    };
+    Ok(PmsServiceClient::with_interceptor(
+        channel,
+        Authorization(authorization),
+    ))
+}
+
+struct SearchOptions {
+    query: String,
+    since: Option<chrono::NaiveDateTime>,
+    until: Option<chrono::NaiveDateTime>,
+    json: bool,
+    // Directory to save the images of the results in
+    save: Option<PathBuf>,
+    // Open the saved images with the default viewer
+    open: bool,
+    mode: Mode,
+}
+
+impl SearchOptions {
+    fn parse(args: &[String]) -> Result<Self, String> {
+        let mut options = SearchOptions {
+            query: String::new(),
+            since: None,
+            until: None,
+            json: false,
+            save: None,
+            open: false,
+            mode: Mode::Keyword,
+        };
+        let mut words = vec![];
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+            match arg.as_str() {
+                "--since" => options.since = Some(parse_range_bound(value()?, false)?),
+                "--until" => options.until = Some(parse_range_bound(value()?, true)?),
+                "--json" => options.json = true,
+                "--save" => options.save = Some(PathBuf::from(value()?)),
+                "--open" => options.open = true,
+                "--mode" => {
+                    options.mode = Mode::from_str_name(&value()?.to_uppercase())
+                        .ok_or("--mode must be keyword, semantic or hybrid")?
+                }
+                flag if flag.starts_with("--") => return Err(format!("Unknown option {}", flag)),
+                word => words.push(word),
+            }
+        }
+        if words.is_empty() {
+            return Err("No query given".to_string());
+        }
+        // The images are only written to disk, and so can only be opened, when saved
+        if options.open && options.save.is_none() {
+            return Err("--open needs --save <dir>".to_string());
+        }
+        options.query = words.join(" ");
+        Ok(options)
+    }
+}
+
+// Up to 80 characters of text around the first query word found in it, on one line
+fn snippet(text: &str, query: &str) -> String {
+    const WIDTH: usize = 80;
+    let text: Vec<char> = text
+        .split_whitespace()
+        .collect::<Vec<_>>()
+        .join(" ")
+        .chars()
+        .collect();
+    let lower: String = text.iter().collect::<String>().to_lowercase();
+    let start = query
+        .split_whitespace()
+        .filter_map(|word| lower.find(&word.to_lowercase()))
+        .min()
+        // Byte offset to char offset; lowercasing can change lengths, so this is approximate
+        .map(|offset| lower[..offset].chars().count())
+        .unwrap_or(0)
+        .saturating_sub(WIDTH / 4)
+        .min(text.len().saturating_sub(WIDTH));
+    let end = (start + WIDTH).min(text.len());
+    let mut snippet: String = text[start..end].iter().collect();
+    if start > 0 {
+        snippet.insert_str(0, "...");
+    }
+    if end < text.len() {
+        snippet.push_str("...");
+    }
+    snippet
+}
+
+// Run a search and print the results, best match first. Times are UTC.
+async fn search(
+    mut client: Client,
+    options: SearchOptions,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let response = client
+        .search_screens(SearchRequest {
+            query: options.query.clone(),
+            start_time: options.since.map(to_timestamp),
+            end_time: options.until.map(to_timestamp),
+            mode: options.mode as i32,
+        })
+        .await?
+        .into_inner();
+    if let Some(dir) = &options.save {
+        std::fs::create_dir_all(dir)?;
+    }
+
+    let mut results = vec![];
+    for screen in response.screens {
+        let datetime = from_timestamp(&screen.time.clone().unwrap_or_default()).unwrap();
+        let image_path = match &options.save {
+            Some(dir) => {
+                let path = dir.join(format!(
+                    "{}-{}.jpg",
+                    datetime.format("%Y%m%d-%H%M%S"),
+                    screen.screen_id
+                ));
+                std::fs::write(&path, &screen.image)?;
+                if options.open {
+                    open_image(&path);
+                }
+                Some(path)
+            }
+            None => None,
+        };
+        if options.json {
+            results.push(serde_json::json!({
+                "id": screen.id,
+                "time": datetime.format("%Y-%m-%dT%H:%M:%S%.fZ").to_string(),
+                "screen_id": screen.screen_id,
+                "text": screen.text,
+                "image": image_path,
+            }));
+        } else {
+            println!(
+                "{}  screen {}  {}",
+                datetime.format("%Y-%m-%d %H:%M:%S"),
+                screen.screen_id,
+                snippet(&screen.text, &options.query)
+            );
+            if let Some(path) = image_path {
+                println!("    {}", path.display());
+            }
+        }
+    }
+    if options.json {
+        println!("{}", serde_json::to_string_pretty(&results)?);
+    }
+    Ok(())
+}
+
+fn open_image(path: &std::path::Path) {
+    let opener = if cfg!(target_os = "macos") {
+        "open"
+    } else {
+        "xdg-open"
+    };
+    if let Err(e) = std::process::Command::new(opener).arg(path).status() {
+        warn!("Could not open {}: {}", path.display(), e);
+    }
+}
 
+async fn capture(mut client: Client) -> Result<(), Box<dyn std::error::Error>> {
     let screen_task = tokio::spawn(async move {
         let mut interval = time::interval(Duration::from_secs(5));
         loop {
@@ -20,16 +246,16 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
             interval.tick().await;
 
             // take screenshots and send
+            let since_epoch = SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .unwrap();
             let screen_time = prost_types::Timestamp {
-                seconds: SystemTime::now()
-                    .duration_since(SystemTime::UNIX_EPOCH)
-                    .unwrap()
-                    .as_secs() as i64,
-                nanos: 0,
+                seconds: since_epoch.as_secs() as i64,
+                nanos: since_epoch.subsec_nanos() as i32,
             };
             let screens = all_screens();
             if screens.len() == 0 {
-                eprintln!("Error: No screens found");
+                error!("No screens found");
                 break;
             }
             for screen in screens {
@@ -57,6 +283,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                     );
                     encoder.encode_image(&image).unwrap();
                 }
+                let jpeg_len = jpeg_data.len();
                 let upload_screen_request = UploadScreenRequest {
                     time: Some(screen_time.clone()),
                     screen_id: screen.id,
@@ -64,9 +291,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
                 };
                 let request =
                     tonic::Request::new(stream::once(async move { upload_screen_request }));
-                match client.upload_screen(request).await {
-                    Ok(_) => continue,
-                    Err(_) => eprintln!("Error sending screen"),
+                let span = info_span!("upload", screen_id = screen.id, bytes = jpeg_len);
+                match client.upload_screen(request).instrument(span.clone()).await {
+                    Ok(response) if response.get_ref().success => {}
+                    Ok(_) => span.in_scope(|| warn!("The server could not store the screen")),
+                    Err(status) => span.in_scope(|| {
+                        error!(code = ?status.code(), "Could not send screen: {}", status.message())
+                    }),
                 }
             } // end for screen in screens
         }
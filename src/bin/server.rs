@@ -1,314 +1,387 @@
-extern crate tantivy;
-#[macro_use]
-extern crate text_io;
-use chrono::Datelike;
-use indicatif::ProgressBar;
-use std::collections::HashSet;
-use std::fs::{create_dir_all, File};
-use std::io::Write;
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use std::time::Duration;
-use tantivy::collector::TopDocs;
-use tantivy::schema::*;
-use tantivy::{Document, Index, IndexWriter};
+use tantivy::IndexWriter;
+use tokio::signal::unix::{signal, SignalKind};
 use tokio::sync::RwLock;
 use tokio::time;
-use tonic::{transport::Server, Request, Response, Status, Streaming};
+use tonic::codegen::InterceptedService;
+use tonic::transport::{Identity, Server, ServerTlsConfig};
+use tracing::{debug_span, error, info, info_span, warn, Instrument};
 use walkdir::WalkDir;
 
-use pms::api;
-use pms::api::pms_service_server::{PmsService, PmsServiceServer};
-use pms::api::{
-    search_response::Screen as SearchResponseScreen, Ack, SearchRequest, SearchResponse,
-    UploadScreenRequest,
-};
-use pms::dhash::{get_dhash, IMG_SIZE};
+use pms::api::pms_service_server::PmsServiceServer;
+use pms::archive::{self, parse_range_bound};
+use pms::auth;
+use pms::config::Config;
+use pms::crypto::{self, Cipher, KeyMaterial};
+use pms::embed::embedder_from_config;
+use pms::index::{make_schema, rebuild_index};
+use pms::journal::{self, Journal, JOURNAL_PATH};
+use pms::logging;
+use pms::metrics::serve_metrics;
+use pms::migrate::prepare_index;
+use pms::ocr::{engine_from_config, OcrEngine, TesseractEngine};
+use pms::preprocess::PreprocessOptions;
+use pms::rest::RestApi;
+use pms::retention::run_retention;
+use pms::service::ImplPMSService;
+use pms::storage::{frame_entry, read_entries, read_file};
+use pms::vectors::{VectorStore, VECTORS_PATH};
+use pms::web::WebLayer;
 
-use leptess::LepTess;
-
-pub struct ImplPMSService {
-    schema: Schema,
-    index: Index,
-    writer_arc: Arc<RwLock<IndexWriter>>,
-    hashes: RwLock<HashSet<[bool; IMG_SIZE]>>,
-}
-
-impl ImplPMSService {
-    fn new(schema: Schema, index: Index, writer_arc: Arc<RwLock<IndexWriter>>) -> Self {
-        ImplPMSService {
-            schema,
-            index,
-            writer_arc,
-            hashes: RwLock::new(HashSet::new()),
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    logging::init();
+    let config = Config::from_env();
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        None => {
+            load_key(&config)?;
+            serve(config).await
+        }
+        Some("ocr-eval") if args.len() == 3 => {
+            load_key(&config)?;
+            ocr_eval(&config, &args[2]);
+            Ok(())
+        }
+        Some("reindex") if args.len() == 2 => {
+            load_key(&config)?;
+            prepare_index(&config, engine_from_config(&config)).await?;
+            info!("Indexing screens missing from the index");
+            let (schema, index) = make_schema();
+            rebuild_index(&config, engine_from_config(&config), &index, &schema).await;
+            Ok(())
+        }
+        Some("export") if args.len() == 5 => {
+            load_key(&config)?;
+            let start = parse_range_bound(&args[2], false)?;
+            let end = parse_range_bound(&args[3], true)?;
+            let count = archive::export(start, end, Path::new(&args[4]))?;
+            info!(screens = count, path = %args[4], "Exported archive");
+            Ok(())
+        }
+        Some("import") if args.len() == 3 => {
+            load_key(&config)?;
+            prepare_index(&config, engine_from_config(&config)).await?;
+            let (imported, skipped) = archive::import(&config, Path::new(&args[2]))?;
+            info!(imported, skipped, "Imported archive");
+            Ok(())
+        }
+        Some("init-key") if args.len() == 2 => init_key(&config),
+        Some("rekey") if args.len() == 2 => rekey(&config),
+        _ => {
+            eprintln!(
+                "Usage: pms-server [reindex | ocr-eval <image dir> | export <start> <end> <file> \
+                 | import <file> | init-key | rekey]"
+            );
+            std::process::exit(2);
         }
     }
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    // if "screenshots/" exist, but "index/" doesn't, rebuild the index
-    if std::path::Path::new(&"screenshots/").exists() && !std::path::Path::new("index/").exists() {
-        println!("Rebuilding index");
-        std::fs::create_dir("index/")?;
-        let (schema, index) = make_schema();
-        rebuild_index(&index, &schema).await;
-    }
+async fn serve(config: Config) -> Result<(), Box<dyn std::error::Error>> {
+    prepare_index(&config, engine_from_config(&config)).await?;
     let (schema, index) = make_schema();
 
     let addr = "[::1]:50001".parse()?;
-    let writer: Arc<RwLock<IndexWriter>> = Arc::new(RwLock::new(index.writer(50_000_000).unwrap()));
-    let service = ImplPMSService::new(schema, index, writer.clone());
+    let mut index_writer = index.writer(50_000_000).unwrap();
+    // Index the screens uploaded after the last commit before a crash
+    let replayed = journal::replay(&config, &schema, &mut index_writer, JOURNAL_PATH)?;
+    if replayed > 0 {
+        info!(
+            screens = replayed,
+            "Recovered screens from the ingest journal"
+        );
+    }
+    let vectors = match embedder_from_config(&config)? {
+        Some(embedder) => Some(Arc::new(VectorStore::open(embedder, &index, &schema)?)),
+        None => None,
+    };
+    let writer: Arc<RwLock<IndexWriter>> = Arc::new(RwLock::new(index_writer));
+    let journal = Arc::new(Journal::open(JOURNAL_PATH)?);
+    let service = Arc::new(ImplPMSService::new(
+        config.clone(),
+        engine_from_config(&config),
+        schema.clone(),
+        index.clone(),
+        writer.clone(),
+        journal.clone(),
+        vectors.clone(),
+    ));
 
-    let server = Server::builder()
+    let mut builder = Server::builder();
+    match (&config.tls_cert, &config.tls_key) {
+        (Some(cert), Some(key)) => {
+            let identity = Identity::from_pem(std::fs::read(cert)?, std::fs::read(key)?);
+            builder = builder.tls_config(ServerTlsConfig::new().identity(identity))?;
+        }
+        (None, None) => {
+            if config.upload_token.is_some()
+                || config.search_token.is_some()
+                || config.delete_token.is_some()
+            {
+                warn!("Tokens are sent unencrypted without TLS");
+            }
+        }
+        _ => return Err("PMS_TLS_CERT and PMS_TLS_KEY must be set together".into()),
+    }
+    if !Path::new(&config.web_dir).join("index.html").exists() {
+        warn!(
+            dir = %config.web_dir,
+            "Web interface not found, build it with `cd web && trunk build --release`"
+        );
+    }
+    let server = builder
         .accept_http1(true)
-        .add_service(tonic_web::enable(PmsServiceServer::new(service)))
-        .serve(addr);
+        .layer(WebLayer::new(
+            Path::new(&config.web_dir),
+            RestApi::new(config.clone(), service.clone()),
+        ))
+        // The web interface sends its token in the authorization header, which CORS
+        // has to allow
+        .add_service(tonic_web::config().allow_headers(["authorization"]).enable(
+            InterceptedService::new(
+                PmsServiceServer::from_arc(service),
+                auth::interceptor(&config),
+            ),
+        ))
+        .serve_with_shutdown(addr, shutdown_signal());
 
+    let metrics_addr = config.metrics_addr.parse()?;
     tokio::spawn(async move {
-        let mut interval = time::interval(Duration::from_secs(30));
-        loop {
-            interval.tick().await;
-            print!("Committing index... ");
-            writer.clone().write().await.commit().unwrap();
-            println!("done.");
+        if let Err(e) = serve_metrics(metrics_addr).await {
+            error!("Metrics endpoint failed: {}", e);
         }
     });
 
-    // join all tasks
-    server.await?;
-
-    Ok(())
-}
-
-fn datetime_to_screen_path(datetime: chrono::NaiveDateTime, screen_id: u32) -> (String, String) {
-    let path = format!(
-        "screenshots/{}/{}/{}/",
-        datetime.year(),
-        datetime.month(),
-        datetime.day()
-    );
-    let fname = format!("{}-{}.jpg", datetime.format("%H%M%S"), screen_id);
-    (path, fname)
-}
-
-fn get_all_datetime_screens(datetime: chrono::NaiveDateTime) -> Vec<String> {
-    // glob all screens matching datetime for all possible screen_ids
-    let (path, fname) = datetime_to_screen_path(datetime, 0);
-    let time = fname.split('-').next().unwrap();
-    glob::glob(&format!("{}/{}-*.jpg", path, time))
-        .unwrap()
-        .map(|x| x.unwrap().to_str().unwrap().to_string())
-        .collect()
-}
-
-#[tonic::async_trait]
-impl PmsService for ImplPMSService {
-    async fn upload_screen(
-        &self,
-        request: Request<Streaming<UploadScreenRequest>>,
-    ) -> Result<Response<Ack>, Status> {
-        let result: Result<(), Status> = {
-            let mut stream = request.into_inner();
-            let req = stream.message().await?.unwrap();
-            let time = req.time.unwrap();
-            let datetime =
-                chrono::NaiveDateTime::from_timestamp_opt(time.seconds, time.nanos as u32).unwrap();
+    if config.retention.is_enabled() {
+        tokio::spawn(run_retention(
+            config.retention.clone(),
+            index,
+            schema,
+            writer.clone(),
+            vectors,
+        ));
+    }
 
-            // Hash the image and check if it's already in the index
-            let dyn_image = image::load_from_memory(&req.image).unwrap();
-            let hash = get_dhash(&dyn_image);
-            {
-                let hashes = self.hashes.read().await;
-                if hashes.contains(&hash) {
-                    return Ok(Response::new(Ack { success: true }));
-                }
+    {
+        let writer = writer.clone();
+        let journal = journal.clone();
+        tokio::spawn(async move {
+            let mut interval = time::interval(Duration::from_secs(30));
+            loop {
+                interval.tick().await;
+                journal
+                    .commit(&writer)
+                    .instrument(debug_span!("commit"))
+                    .await
+                    .unwrap();
             }
+        });
+    }
 
-            // OCR the image
-            let text = ocr_image_mem(&req.image);
-
-            // Save the image
-            let (path, fname) = datetime_to_screen_path(datetime, req.screen_id);
-            create_dir_all(&path)?; // Not handled on purpose
-            let mut file = File::create(path + &fname).unwrap();
-            file.write_all(&req.image).unwrap();
-
-            // Index the image
-            let mut doc = Document::default();
-            doc.add_text(self.schema.get_field("text").unwrap(), &text);
-            doc.add_date(
-                self.schema.get_field("date").unwrap(),
-                tantivy::DateTime::from_timestamp_secs(datetime.timestamp()),
-            );
-            doc.add_u64(
-                self.schema.get_field("screen_id").unwrap(),
-                req.screen_id as u64,
-            );
-            let index_writer = self.writer_arc.read().await;
-            index_writer.add_document(doc).unwrap();
+    // Runs until a shutdown signal, then waits for in-flight requests to finish
+    server.await?;
 
-            {
-                // Add the hash to the set
-                let mut hashes = self.hashes.write().await;
-                hashes.insert(hash);
-            }
+    journal
+        .commit(&writer)
+        .instrument(info_span!("commit"))
+        .await?;
 
-            Ok(())
-        };
-        let reply = match result {
-            Ok(_) => Ack { success: true },
-            Err(_) => Ack { success: false },
-        };
+    Ok(())
+}
 
-        Ok(Response::new(reply))
+// Resolves on SIGINT or SIGTERM
+async fn shutdown_signal() {
+    let mut terminate = signal(SignalKind::terminate()).unwrap();
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {}
+        _ = terminate.recv() => {}
     }
+    info!("Shutting down");
+}
 
-    async fn search_screens(
-        &self,
-        request: Request<SearchRequest>,
-    ) -> Result<Response<SearchResponse>, Status> {
-        let result: Result<SearchResponse, Status> = {
-            let req = request.into_inner();
-            println!("Searching for {}", req.query);
-            let query_parser = tantivy::query::QueryParser::for_index(
-                &self.index,
-                vec![self.schema.get_field("text").unwrap()],
-            );
-            let reader = self
-                .index
-                .reader_builder()
-                .reload_policy(tantivy::ReloadPolicy::OnCommit)
-                .try_into()
-                .unwrap();
-            let searcher = reader.searcher();
-            let query = query_parser.parse_query(&req.query).unwrap();
-            let top_docs = searcher.search(&query, &TopDocs::with_limit(200)).unwrap();
-            let mut screens: Vec<SearchResponseScreen> = vec![];
-            println!("Found {} results", top_docs.len());
-            for (_score, doc_address) in top_docs {
-                let retrieved_doc = searcher.doc(doc_address).unwrap();
-
-                let text = retrieved_doc
-                    .get_first(self.schema.get_field("text").unwrap())
-                    .unwrap()
-                    .as_text()
-                    .unwrap();
-                let date = retrieved_doc
-                    .get_first(self.schema.get_field("date").unwrap())
-                    .unwrap()
-                    .as_date()
-                    .unwrap();
-                //let screen_id = retrieved_doc.get_first(self.schema.get_field("screen_id").unwrap()).unwrap_or(&tantivy::schema::Value::U64(0)).as_u64().unwrap();
-                let screen_id = retrieved_doc
-                    .get_first(self.schema.get_field("screen_id").unwrap())
-                    .unwrap()
-                    .as_u64()
-                    .unwrap();
-                let (image_path, image_fname) = datetime_to_screen_path(
-                    chrono::NaiveDateTime::from_timestamp_opt(date.into_timestamp_secs(), 0)
-                        .unwrap(),
-                    screen_id as u32,
-                );
-                let image_full_path = image_path + &image_fname;
-                screens.push(SearchResponseScreen {
-                    screen_id: screen_id as u32,
-                    image: std::fs::read(image_full_path).unwrap(),
-                    text: text.to_string(),
-                    time: Some(prost_types::Timestamp {
-                        seconds: date.into_timestamp_secs(),
-                        nanos: 0,
-                    }),
-                });
+// Compare OCR output with and without the configured preprocessing on a set of sample
+// images, reporting the number of words found and their mean confidence.
+fn ocr_eval(config: &Config, dir: &str) {
+    let raw = TesseractEngine::new(&config.ocr_languages, PreprocessOptions::default());
+    let processed = TesseractEngine::new(&config.ocr_languages, config.preprocess.clone());
+    println!("Preprocessing: {:?}", config.preprocess);
+    println!(
+        "{:<40} {:>10} {:>10} {:>10} {:>10}",
+        "image", "raw words", "raw conf", "words", "conf"
+    );
+    let mut totals = [(0, 0.0), (0, 0.0)];
+    for entry in WalkDir::new(dir).sort_by_file_name() {
+        let entry = entry.unwrap();
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let image = match read_file(entry.path()) {
+            Ok(image) if image::load_from_memory(&image).is_ok() => image,
+            _ => {
+                warn!(path = %entry.path().display(), "Skipping a file that is not an image");
+                continue;
             }
-            Ok(api::SearchResponse { screens })
         };
-        Ok(Response::new(result.unwrap()))
+        let results = [raw.recognize(&image), processed.recognize(&image)];
+        let mut row = format!("{:<40}", entry.file_name().to_string_lossy());
+        for (total, result) in totals.iter_mut().zip(results.iter()) {
+            let confidence: f32 = result.words.iter().map(|w| w.confidence).sum();
+            total.0 += result.words.len();
+            total.1 += confidence;
+            row += &format!(
+                " {:>10} {:>10.1}",
+                result.words.len(),
+                confidence / result.words.len().max(1) as f32
+            );
+        }
+        println!("{}", row);
     }
+    let mut row = format!("{:<40}", "total");
+    for total in totals {
+        row += &format!(" {:>10} {:>10.1}", total.0, total.1 / total.0.max(1) as f32);
+    }
+    println!("{}", row);
 }
 
-fn ocr_image_mem(image: &[u8]) -> String {
-    let mut tess = LepTess::new(None, "eng").unwrap();
-    tess.set_image_from_mem(&image).unwrap();
-    tess.get_utf8_text().unwrap()
+// The passphrase for the key file, from PMS_PASSPHRASE or asked for on the terminal
+fn passphrase(prompt: &str) -> std::io::Result<String> {
+    match std::env::var("PMS_PASSPHRASE") {
+        Ok(passphrase) => Ok(passphrase),
+        Err(_) => rpassword::prompt_password(prompt),
+    }
 }
 
-fn ocr_image_path(path: &str) -> String {
-    let mut tess = LepTess::new(None, "eng").unwrap();
-    tess.set_image(path).unwrap();
-    tess.get_utf8_text().unwrap()
+// Ask for a new passphrase twice
+fn new_passphrase() -> Result<String, Box<dyn std::error::Error>> {
+    let passphrase = rpassword::prompt_password("New passphrase: ")?;
+    if passphrase != rpassword::prompt_password("Repeat passphrase: ")? {
+        return Err("Passphrases do not match".into());
+    }
+    Ok(passphrase)
 }
 
-fn index_image(
-    schema: &Schema,
-    writer: tokio::sync::RwLockReadGuard<'_, tantivy::IndexWriter>,
-    path: &str,
-) {
-    let mut doc = Document::default();
-    let text = ocr_image_path(path);
-    doc.add_text(schema.get_field("text").unwrap(), &text);
-    let year: u32;
-    let month: u32;
-    let day: u32;
-    let time: String;
-    let screen_id: u64;
-    scan!(path.bytes() => "screenshots/{}/{}/{}/{}-{}.jpg", year, month, day, time, screen_id);
-    let time = chrono::NaiveTime::parse_from_str(&time, "%H%M%S").unwrap();
-    let datetime = chrono::NaiveDate::from_ymd_opt(year as i32, month, day)
-        .unwrap()
-        .and_time(time);
-    doc.add_date(
-        schema.get_field("date").unwrap(),
-        tantivy::DateTime::from_timestamp_secs(datetime.timestamp()),
-    );
-    doc.add_u64(schema.get_field("screen_id").unwrap(), screen_id);
-    writer.add_document(doc).unwrap();
+// Enable encryption at rest if there is a key file
+fn load_key(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(&config.key_file);
+    if path.exists() {
+        let key = crypto::read_key_file(path, &passphrase("Passphrase: ")?)?;
+        crypto::init(key.cipher());
+        info!("Encryption at rest enabled");
+    }
+    Ok(())
 }
 
-async fn rebuild_index(index: &Index, schema: &Schema) {
-    let writer_arc: Arc<RwLock<IndexWriter>> =
-        Arc::new(RwLock::new(index.writer(50_000_000).unwrap()));
-    let schema_arc = Arc::new(schema.clone());
-    let mut handles = vec![];
-    let pb = Arc::new(RwLock::new(ProgressBar::new(0)));
-    for entry in WalkDir::new("screenshots") {
-        let my_schema = Arc::clone(&schema_arc);
-        let my_writer = Arc::clone(&writer_arc);
-        let my_pb = Arc::clone(&pb);
-        let job = tokio::spawn(async move {
-            let entry = entry.unwrap();
-            if entry.file_type().is_file() {
-                index_image(
-                    &*my_schema,
-                    my_writer.clone().read().await,
-                    entry.path().to_str().unwrap(),
-                );
-            }
-            my_pb.read().await.inc(1);
-        });
-        handles.push(job);
+// Create a key file. Data stored from then on is encrypted; run rekey to also encrypt
+// what was stored before.
+fn init_key(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(&config.key_file);
+    if path.exists() {
+        return Err(format!(
+            "{} already exists, use rekey to change the key",
+            path.display()
+        )
+        .into());
     }
-    let count = handles.len();
-    pb.read().await.inc_length(count as u64);
-    futures_util::future::join_all(handles).await;
-    pb.read().await.finish();
-    writer_arc.clone().write().await.commit().unwrap();
-    println!("Done: Indexed {} images", count);
+    crypto::write_key_file(path, &KeyMaterial::generate(), &new_passphrase()?)?;
+    info!(path = %path.display(), "Wrote key file");
+    Ok(())
 }
 
-fn make_schema() -> (Schema, Index) {
-    const INDEX_PATH: &str = "index/";
-    if !std::path::Path::new(INDEX_PATH).exists() {
-        std::fs::create_dir(INDEX_PATH).unwrap();
+// Re-encrypt all stored data with a new key and passphrase, encrypting any data that
+// is still plaintext along the way. The new key is kept next to the old one until every
+// file has been converted, so an interrupted run can be resumed by running it again.
+// The server must not be running.
+fn rekey(config: &Config) -> Result<(), Box<dyn std::error::Error>> {
+    let path = Path::new(&config.key_file);
+    let old = crypto::read_key_file(path, &passphrase("Current passphrase: ")?)?.cipher();
+    let mut new_path = path.as_os_str().to_owned();
+    new_path.push(".new");
+    let new_path = PathBuf::from(new_path);
+    let new = if new_path.exists() {
+        info!("Resuming an interrupted rekey");
+        crypto::read_key_file(&new_path, &rpassword::prompt_password("New passphrase: ")?)?
+    } else {
+        let key = KeyMaterial::generate();
+        crypto::write_key_file(&new_path, &key, &new_passphrase()?)?;
+        key
     }
-    let mut schema_builder = SchemaBuilder::default();
-    let _date = schema_builder.add_date_field("date", STORED);
-    let _text = schema_builder.add_text_field("text", TEXT | STORED);
-    let _screen_id = schema_builder.add_u64_field("screen_id", STORED);
-    let schema = schema_builder.build();
+    .cipher();
 
-    // Create or open the tantivy index
-    let dir = tantivy::directory::MmapDirectory::open(INDEX_PATH).unwrap();
-    let index = Index::open_or_create(dir, schema.clone()).unwrap();
+    let mut count = 0;
+    for entry in WalkDir::new("screenshots")
+        .into_iter()
+        .chain(WalkDir::new("index"))
+    {
+        let entry = entry?;
+        let file = entry.path();
+        let name = entry.file_name().to_string_lossy();
+        // Lock files are not written through the encrypted directory
+        if !entry.file_type().is_file() || name.starts_with(".tantivy-") {
+            continue;
+        }
+        // Left behind by an interrupted run
+        if name.ends_with(".rekey") {
+            std::fs::remove_file(file)?;
+            continue;
+        }
+        let data = std::fs::read(file)?;
+        if new.owns(&data) {
+            continue;
+        }
+        let plaintext = if crypto::is_encrypted(&data) {
+            old.decrypt(&data)
+                .map_err(|e| format!("{}: {}", file.display(), e))?
+        } else {
+            data
+        };
+        // Replace the file atomically, so it is never lost or half-written
+        let mut tmp = file.as_os_str().to_owned();
+        tmp.push(".rekey");
+        std::fs::write(&tmp, new.encrypt(&plaintext))?;
+        std::fs::rename(&tmp, file)?;
+        count += 1;
+    }
+    for file in [JOURNAL_PATH, VECTORS_PATH] {
+        if rekey_entries(Path::new(file), &old, &new)? {
+            count += 1;
+        }
+    }
+    std::fs::rename(&new_path, path)?;
+    info!(files = count, "Re-encrypted stored data");
+    Ok(())
+}
 
-    (schema, index)
+// Re-encrypt the entries of the journal or the vector file, which are encrypted one by
+// one rather than as a whole. Returns whether the file was rewritten.
+fn rekey_entries(
+    file: &Path,
+    old: &Cipher,
+    new: &Cipher,
+) -> Result<bool, Box<dyn std::error::Error>> {
+    let entries = read_entries(file)?;
+    if entries.iter().all(|entry| new.owns(entry)) {
+        return Ok(false);
+    }
+    let mut rekeyed = vec![];
+    for entry in entries {
+        let entry = if new.owns(&entry) {
+            entry
+        } else if crypto::is_encrypted(&entry) {
+            let plaintext = old
+                .decrypt(&entry)
+                .map_err(|e| format!("{}: {}", file.display(), e))?;
+            new.encrypt(&plaintext)
+        } else {
+            new.encrypt(&entry)
+        };
+        rekeyed.extend(frame_entry(&entry));
+    }
+    let mut tmp = file.as_os_str().to_owned();
+    tmp.push(".rekey");
+    std::fs::write(&tmp, rekeyed)?;
+    std::fs::rename(&tmp, file)?;
+    Ok(true)
 }
@@ -0,0 +1,136 @@
+use std::str::FromStr;
+
+use crate::preprocess::PreprocessOptions;
+use crate::retention::RetentionPolicy;
+
+// Server configuration, read from PMS_* environment variables with sensible defaults
+#[derive(Clone, Debug)]
+pub struct Config {
+    // OCR engine: "tesseract", or "fixture" for a deterministic engine that needs no OCR
+    pub ocr_engine: String,
+    // Text the fixture engine returns for images without a .txt sidecar
+    pub ocr_fixture_text: String,
+    // Tesseract language set, e.g. "eng+dan+deu"
+    pub ocr_languages: String,
+    // Detect the language of each screen's text and index it with a matching stemmer
+    pub detect_language: bool,
+    // Image preprocessing steps to run before OCR
+    pub preprocess: PreprocessOptions,
+    // Number of uploads OCRed at the same time; further uploads wait in line
+    pub ocr_concurrency: usize,
+    pub retention: RetentionPolicy,
+    // Number of images OCRed at the same time when rebuilding the index
+    pub reindex_concurrency: usize,
+    // Commit the index after this many images when rebuilding it
+    pub reindex_checkpoint: usize,
+    // Key file for encryption at rest. Stored data is encrypted if this file exists.
+    pub key_file: String,
+    // PEM certificate and private key to serve over TLS; plain HTTP/2 if unset
+    pub tls_cert: Option<String>,
+    pub tls_key: Option<String>,
+    // Bearer tokens for uploading and for searching. A scope without a token is open.
+    pub upload_token: Option<String>,
+    pub search_token: Option<String>,
+    // Bearer token for deleting screens. Without it, deleting is only possible when no
+    // token is configured at all.
+    pub delete_token: Option<String>,
+    // Address of the Prometheus /metrics endpoint
+    pub metrics_addr: String,
+    // Built web interface, served on the API port
+    pub web_dir: String,
+    // Text embedder for semantic search: "none", "word-vectors", or "hashing" for tests
+    pub embedder: String,
+    // Word vector file for the word-vectors embedder
+    pub embedding_model: Option<String>,
+    // Vector size of the hashing embedder
+    pub embedding_dimensions: usize,
+}
+
+impl Config {
+    pub fn from_env() -> Self {
+        Config {
+            ocr_engine: env_or("PMS_OCR_ENGINE", "tesseract".to_string()),
+            ocr_fixture_text: env_or("PMS_OCR_FIXTURE_TEXT", String::new()),
+            ocr_languages: env_or("PMS_OCR_LANGUAGES", "eng".to_string()),
+            detect_language: env_or("PMS_DETECT_LANGUAGE", true),
+            preprocess: env_or("PMS_OCR_PREPROCESS", PreprocessOptions::default()),
+            ocr_concurrency: env_nonzero(
+                "PMS_OCR_CONCURRENCY",
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ),
+            retention: RetentionPolicy {
+                max_age_days: env_opt("PMS_RETENTION_MAX_AGE_DAYS"),
+                max_bytes: env_opt("PMS_RETENTION_MAX_BYTES"),
+                max_per_screen: env_opt_nonzero("PMS_RETENTION_MAX_PER_SCREEN"),
+                interval_secs: env_nonzero("PMS_RETENTION_INTERVAL_SECS", 3600),
+            },
+            reindex_concurrency: env_or(
+                "PMS_REINDEX_CONCURRENCY",
+                std::thread::available_parallelism().map_or(1, |n| n.get()),
+            ),
+            reindex_checkpoint: env_nonzero("PMS_REINDEX_CHECKPOINT", 500),
+            key_file: env_or("PMS_KEY_FILE", "pms.key".to_string()),
+            tls_cert: env_opt("PMS_TLS_CERT"),
+            tls_key: env_opt("PMS_TLS_KEY"),
+            upload_token: env_opt("PMS_UPLOAD_TOKEN"),
+            search_token: env_opt("PMS_SEARCH_TOKEN"),
+            delete_token: env_opt("PMS_DELETE_TOKEN"),
+            metrics_addr: env_or("PMS_METRICS_ADDR", "[::1]:50002".to_string()),
+            web_dir: env_or("PMS_WEB_DIR", "web/dist".to_string()),
+            embedder: env_or("PMS_EMBEDDER", "none".to_string()),
+            embedding_model: env_opt("PMS_EMBEDDING_MODEL"),
+            embedding_dimensions: env_nonzero("PMS_EMBEDDING_DIMENSIONS", 256),
+        }
+    }
+
+    // The individual Tesseract language codes, e.g. ["eng", "dan", "deu"]
+    pub fn ocr_language_codes(&self) -> Vec<&str> {
+        self.ocr_languages
+            .split('+')
+            .map(str::trim)
+            .filter(|code| !code.is_empty())
+            .collect()
+    }
+}
+
+fn env_or<T: FromStr>(name: &str, default: T) -> T
+where
+    T::Err: std::fmt::Debug,
+{
+    env_opt(name).unwrap_or(default)
+}
+
+// Like env_or, for settings that can't be 0
+fn env_nonzero<T: FromStr + Default + PartialEq>(name: &str, default: T) -> T
+where
+    T::Err: std::fmt::Debug,
+{
+    let value = env_or(name, default);
+    if value == T::default() {
+        panic!("Invalid value for {}: must not be 0", name);
+    }
+    value
+}
+
+// Like env_opt, for settings that can't be 0
+fn env_opt_nonzero<T: FromStr + Default + PartialEq>(name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Debug,
+{
+    let value = env_opt(name);
+    if value == Some(T::default()) {
+        panic!("Invalid value for {}: must not be 0", name);
+    }
+    value
+}
+
+fn env_opt<T: FromStr>(name: &str) -> Option<T>
+where
+    T::Err: std::fmt::Debug,
+{
+    std::env::var(name).ok().map(|value| {
+        value
+            .parse()
+            .unwrap_or_else(|e| panic!("Invalid value for {}: {:?} ({:?})", name, value, e))
+    })
+}
@@ -0,0 +1,269 @@
+use argon2::Argon2;
+use chacha20poly1305::aead::{Aead, Payload};
+use chacha20poly1305::{ChaCha20Poly1305, Key, KeyInit, Nonce};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use std::io;
+use std::path::Path;
+use std::sync::OnceLock;
+
+// Encryption at rest. Encrypted files consist of a header followed by the plaintext in
+// CHUNK_SIZE chunks, each encrypted with ChaCha20-Poly1305 separately so that any part
+// of a file can be decrypted without reading all of it (needed for the index).
+//
+// header: MAGIC | VERSION | key id (8 bytes) | nonce prefix (8 bytes)
+// chunk:  ciphertext | tag (16 bytes), nonce = nonce prefix | chunk number (u32, BE)
+//
+// The associated data of a chunk marks whether it is the last one, so a truncated
+// file does not decrypt. Files without the header are read as plaintext, so an
+// existing archive keeps working after encryption is enabled.
+
+const MAGIC: &[u8; 4] = b"PMSE";
+const VERSION: u8 = 1;
+pub const HEADER_LEN: usize = MAGIC.len() + 1 + 8 + 8;
+pub const CHUNK_SIZE: usize = 64 * 1024;
+const TAG_LEN: usize = 16;
+pub const ENCRYPTED_CHUNK_SIZE: usize = CHUNK_SIZE + TAG_LEN;
+
+// The data key is stored in the key file, encrypted with a key derived from a passphrase
+// (Argon2id): KEY_MAGIC | VERSION | key id (8) | salt (16) | nonce (12) | data key + tag
+const KEY_MAGIC: &[u8; 4] = b"PMSK";
+const KEY_FILE_LEN: usize = KEY_MAGIC.len() + 1 + 8 + 16 + 12 + 32 + TAG_LEN;
+
+static CIPHER: OnceLock<Cipher> = OnceLock::new();
+
+// Enable encryption at rest for this process
+pub fn init(cipher: Cipher) {
+    if CIPHER.set(cipher).is_err() {
+        panic!("Encryption was already initialised");
+    }
+}
+
+// The cipher used for stored data, if encryption at rest is enabled
+pub fn cipher() -> Option<&'static Cipher> {
+    CIPHER.get()
+}
+
+pub fn is_encrypted(data: &[u8]) -> bool {
+    data.len() >= HEADER_LEN && &data[..MAGIC.len()] == MAGIC
+}
+
+// Length of the plaintext of an encrypted file with the given total length
+pub fn plaintext_len(encrypted_len: usize) -> usize {
+    let body = encrypted_len.saturating_sub(HEADER_LEN);
+    let chunks = (body + ENCRYPTED_CHUNK_SIZE - 1) / ENCRYPTED_CHUNK_SIZE;
+    body.saturating_sub(chunks * TAG_LEN)
+}
+
+pub struct Cipher {
+    key_id: [u8; 8],
+    aead: ChaCha20Poly1305,
+}
+
+impl Cipher {
+    fn new(key_id: [u8; 8], key: &[u8; 32]) -> Self {
+        Cipher {
+            key_id,
+            aead: ChaCha20Poly1305::new(Key::from_slice(key)),
+        }
+    }
+
+    // Whether an encrypted file was encrypted with this key
+    pub fn owns(&self, data: &[u8]) -> bool {
+        is_encrypted(data) && data[MAGIC.len() + 1..MAGIC.len() + 9] == self.key_id
+    }
+
+    pub fn header(&self) -> [u8; HEADER_LEN] {
+        let mut header = [0; HEADER_LEN];
+        header[..MAGIC.len()].copy_from_slice(MAGIC);
+        header[MAGIC.len()] = VERSION;
+        header[MAGIC.len() + 1..MAGIC.len() + 9].copy_from_slice(&self.key_id);
+        OsRng.fill_bytes(&mut header[MAGIC.len() + 9..]);
+        header
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Vec<u8> {
+        let header = self.header();
+        let mut data = header.to_vec();
+        let mut chunks = plaintext.chunks(CHUNK_SIZE).peekable();
+        if chunks.peek().is_none() {
+            data.extend(self.encrypt_chunk(&header, 0, true, &[]));
+        }
+        let mut index = 0;
+        while let Some(chunk) = chunks.next() {
+            let last = chunks.peek().is_none();
+            data.extend(self.encrypt_chunk(&header, index, last, chunk));
+            index += 1;
+        }
+        data
+    }
+
+    pub fn decrypt(&self, data: &[u8]) -> io::Result<Vec<u8>> {
+        self.check_header(data)?;
+        let header = &data[..HEADER_LEN];
+        let body = &data[HEADER_LEN..];
+        let count = body.chunks(ENCRYPTED_CHUNK_SIZE).count();
+        let mut plaintext = Vec::with_capacity(plaintext_len(data.len()));
+        for (index, chunk) in body.chunks(ENCRYPTED_CHUNK_SIZE).enumerate() {
+            plaintext.extend(self.decrypt_chunk(
+                header,
+                index as u32,
+                index + 1 == count,
+                chunk,
+            )?);
+        }
+        Ok(plaintext)
+    }
+
+    pub fn check_header(&self, data: &[u8]) -> io::Result<()> {
+        if !is_encrypted(data) || data[MAGIC.len()] != VERSION {
+            return Err(invalid_data("Not an encrypted file"));
+        }
+        if !self.owns(data) {
+            return Err(invalid_data("File was encrypted with a different key"));
+        }
+        Ok(())
+    }
+
+    pub fn encrypt_chunk(&self, header: &[u8], index: u32, last: bool, chunk: &[u8]) -> Vec<u8> {
+        let payload = Payload {
+            msg: chunk,
+            aad: &[last as u8],
+        };
+        self.aead
+            .encrypt(&chunk_nonce(header, index), payload)
+            .unwrap()
+    }
+
+    pub fn decrypt_chunk(
+        &self,
+        header: &[u8],
+        index: u32,
+        last: bool,
+        chunk: &[u8],
+    ) -> io::Result<Vec<u8>> {
+        let payload = Payload {
+            msg: chunk,
+            aad: &[last as u8],
+        };
+        self.aead
+            .decrypt(&chunk_nonce(header, index), payload)
+            .map_err(|_| invalid_data("Decryption failed, the file is corrupt or truncated"))
+    }
+}
+
+fn chunk_nonce(header: &[u8], index: u32) -> Nonce {
+    let mut nonce = [0; 12];
+    nonce[..8].copy_from_slice(&header[MAGIC.len() + 9..HEADER_LEN]);
+    nonce[8..].copy_from_slice(&index.to_be_bytes());
+    *Nonce::from_slice(&nonce)
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn passphrase_key(passphrase: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = [0; 32];
+    Argon2::default()
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .unwrap();
+    ChaCha20Poly1305::new(Key::from_slice(&key))
+}
+
+// Write a key file holding the data key, protected by the passphrase
+pub fn write_key_file(path: &Path, key: &KeyMaterial, passphrase: &str) -> io::Result<()> {
+    let mut salt = [0; 16];
+    let mut nonce = [0; 12];
+    OsRng.fill_bytes(&mut salt);
+    OsRng.fill_bytes(&mut nonce);
+    let wrapped = passphrase_key(passphrase, &salt)
+        .encrypt(Nonce::from_slice(&nonce), &key.key[..])
+        .unwrap();
+
+    let mut data = KEY_MAGIC.to_vec();
+    data.push(VERSION);
+    data.extend(key.key_id);
+    data.extend(salt);
+    data.extend(nonce);
+    data.extend(wrapped);
+    // Write to a temporary file first, so a crash never leaves a partial key file behind
+    let tmp = path.with_extension("tmp");
+    std::fs::write(&tmp, data)?;
+    std::fs::rename(tmp, path)
+}
+
+pub fn read_key_file(path: &Path, passphrase: &str) -> io::Result<KeyMaterial> {
+    let data = std::fs::read(path)?;
+    if data.len() != KEY_FILE_LEN || &data[..KEY_MAGIC.len()] != KEY_MAGIC {
+        return Err(invalid_data("Not a key file"));
+    }
+    let data = &data[KEY_MAGIC.len() + 1..];
+    let (key_id, data) = data.split_at(8);
+    let (salt, data) = data.split_at(16);
+    let (nonce, wrapped) = data.split_at(12);
+    let key = passphrase_key(passphrase, salt)
+        .decrypt(Nonce::from_slice(nonce), wrapped)
+        .map_err(|_| invalid_data("Wrong passphrase"))?;
+    Ok(KeyMaterial {
+        key_id: key_id.try_into().unwrap(),
+        key: key.try_into().unwrap(),
+    })
+}
+
+// A data key and its id, as stored in the key file
+pub struct KeyMaterial {
+    key_id: [u8; 8],
+    key: [u8; 32],
+}
+
+impl KeyMaterial {
+    pub fn generate() -> Self {
+        let mut key_id = [0; 8];
+        let mut key = [0; 32];
+        OsRng.fill_bytes(&mut key_id);
+        OsRng.fill_bytes(&mut key);
+        KeyMaterial { key_id, key }
+    }
+
+    pub fn cipher(&self) -> Cipher {
+        Cipher::new(self.key_id, &self.key)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let cipher = KeyMaterial::generate().cipher();
+        for len in [0, 1, CHUNK_SIZE, 2 * CHUNK_SIZE + 100] {
+            let plaintext: Vec<u8> = (0..len).map(|i| i as u8).collect();
+            let encrypted = cipher.encrypt(&plaintext);
+            assert!(is_encrypted(&encrypted));
+            assert!(cipher.owns(&encrypted));
+            assert_eq!(plaintext_len(encrypted.len()), len);
+            assert_eq!(cipher.decrypt(&encrypted).unwrap(), plaintext);
+        }
+    }
+
+    #[test]
+    fn rejects_truncated_file() {
+        let cipher = KeyMaterial::generate().cipher();
+        let encrypted = cipher.encrypt(&vec![7; 2 * CHUNK_SIZE + 100]);
+        // Cut off the last chunk, so the file ends on a whole chunk
+        let truncated = &encrypted[..HEADER_LEN + 2 * ENCRYPTED_CHUNK_SIZE];
+        assert!(cipher.decrypt(truncated).is_err());
+        // Cut off part of the last chunk
+        assert!(cipher.decrypt(&encrypted[..encrypted.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn rejects_other_key() {
+        let encrypted = KeyMaterial::generate().cipher().encrypt(b"screen");
+        let other = KeyMaterial::generate().cipher();
+        assert!(!other.owns(&encrypted));
+        assert!(other.decrypt(&encrypted).is_err());
+    }
+}
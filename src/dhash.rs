@@ -40,3 +40,38 @@ pub fn to_grey_signature_image<I: GenericImageView + 'static>(
 
     return signature_image;
 }
+
+// Packed form of a hash, 8 bits per byte, as stored in the index
+pub fn dhash_to_bytes(bits: &[bool; IMG_SIZE]) -> Vec<u8> {
+    bits.chunks(8)
+        .map(|byte| byte.iter().fold(0, |acc, &bit| acc << 1 | bit as u8))
+        .collect()
+}
+
+// Hex form of a packed hash
+pub fn packed_dhash_to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// Packed hash from its hex form, or None if it is not valid hex
+pub fn packed_dhash_from_hex(hex: &str) -> Option<Vec<u8>> {
+    if hex.len() % 2 != 0 {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+// Packed hash of an encoded (e.g. JPEG) image, or None if it can't be decoded
+pub fn image_dhash(image: &[u8]) -> Option<Vec<u8>> {
+    let image = image::load_from_memory(image).ok()?;
+    Some(dhash_to_bytes(&get_dhash(&image)))
+}
+
+// Number of differing bits between two packed hashes. Visually similar images have a
+// small distance.
+pub fn hamming_distance(a: &[u8], b: &[u8]) -> u32 {
+    a.iter().zip(b).map(|(x, y)| (x ^ y).count_ones()).sum()
+}
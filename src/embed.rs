@@ -0,0 +1,175 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::info;
+
+use crate::config::Config;
+
+// Something that can turn text into a vector, such that texts about the same thing get
+// vectors pointing the same way. Vectors are L2-normalized, so their dot product is the
+// cosine similarity.
+pub trait Embedder: Send + Sync {
+    fn dimensions(&self) -> usize;
+
+    // Identifies the embedder and its model; stored vectors from a different one are
+    // computed again
+    fn name(&self) -> String;
+
+    // None if the text has nothing to embed, e.g. no known words
+    fn embed(&self, text: &str) -> Option<Vec<f32>>;
+}
+
+// Create the embedder selected by PMS_EMBEDDER, or None if semantic search is disabled
+pub fn embedder_from_config(config: &Config) -> io::Result<Option<Arc<dyn Embedder>>> {
+    match config.embedder.as_str() {
+        "none" => Ok(None),
+        "hashing" => Ok(Some(Arc::new(HashingEmbedder::new(
+            config.embedding_dimensions,
+        )))),
+        "word-vectors" => {
+            let path = config.embedding_model.as_ref().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "PMS_EMBEDDING_MODEL must be set for the word-vectors embedder",
+                )
+            })?;
+            Ok(Some(Arc::new(WordVectorEmbedder::load(Path::new(path))?)))
+        }
+        other => panic!("Unknown embedder: {}", other),
+    }
+}
+
+fn words(text: &str) -> impl Iterator<Item = String> + '_ {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(str::to_lowercase)
+}
+
+fn normalize(mut vector: Vec<f32>) -> Option<Vec<f32>> {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm == 0.0 {
+        return None;
+    }
+    vector.iter_mut().for_each(|x| *x /= norm);
+    Some(vector)
+}
+
+// FNV-1a, which unlike the std hasher is the same across builds and platforms
+fn fnv1a(data: &[u8]) -> u64 {
+    data.iter().fold(0xcbf29ce484222325, |hash, &byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+// Deterministic embedder hashing each word to a dimension. It only matches texts sharing
+// words, so it is no better than keyword search, but needs no model: for tests and
+// development.
+pub struct HashingEmbedder {
+    dimensions: usize,
+}
+
+impl HashingEmbedder {
+    pub fn new(dimensions: usize) -> Self {
+        HashingEmbedder { dimensions }
+    }
+}
+
+impl Embedder for HashingEmbedder {
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn name(&self) -> String {
+        format!("hashing-{}", self.dimensions)
+    }
+
+    fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        let mut vector = vec![0.0; self.dimensions];
+        for word in words(text) {
+            let hash = fnv1a(word.as_bytes());
+            // The top bit decides the sign, so colliding words tend to cancel out
+            let sign = if hash >> 63 == 0 { 1.0 } else { -1.0 };
+            vector[(hash % self.dimensions as u64) as usize] += sign;
+        }
+        normalize(vector)
+    }
+}
+
+// Embeds a text as the mean of the pretrained vectors of its words, from a word vector
+// file in the text format of fastText (.vec) or GloVe: a word and its values per line,
+// optionally after a "<count> <dimensions>" header. Runs on the CPU and offline. Words
+// that are not in the file are ignored.
+pub struct WordVectorEmbedder {
+    name: String,
+    dimensions: usize,
+    vectors: HashMap<String, Vec<f32>>,
+}
+
+impl WordVectorEmbedder {
+    pub fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |line: usize| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("{}:{}: invalid word vector", path.display(), line + 1),
+            )
+        };
+        let mut vectors = HashMap::new();
+        let mut dimensions = 0;
+        for (number, line) in BufReader::new(File::open(path)?).lines().enumerate() {
+            let line = line?;
+            let mut fields = line.trim_end().split(' ');
+            let word = fields.next().unwrap_or_default();
+            let values: Vec<f32> = fields
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|_| invalid(number))?;
+            // fastText header
+            if number == 0 && values.len() == 1 {
+                continue;
+            }
+            if dimensions == 0 {
+                dimensions = values.len();
+            }
+            if values.len() != dimensions || dimensions == 0 {
+                return Err(invalid(number));
+            }
+            vectors.insert(word.to_lowercase(), values);
+        }
+        info!(words = vectors.len(), dimensions, "Loaded word vectors");
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy();
+        Ok(WordVectorEmbedder {
+            name: format!("word-vectors-{}-{}", file_name, dimensions),
+            dimensions,
+            vectors,
+        })
+    }
+}
+
+impl Embedder for WordVectorEmbedder {
+    fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn name(&self) -> String {
+        self.name.clone()
+    }
+
+    fn embed(&self, text: &str) -> Option<Vec<f32>> {
+        let mut sum = vec![0.0; self.dimensions];
+        for vector in words(text).filter_map(|word| self.vectors.get(&word)) {
+            // Normalized first, so frequent words with long vectors do not dominate
+            let norm = vector
+                .iter()
+                .map(|x| x * x)
+                .sum::<f32>()
+                .sqrt()
+                .max(f32::EPSILON);
+            for (total, value) in sum.iter_mut().zip(vector) {
+                *total += value / norm;
+            }
+        }
+        normalize(sum)
+    }
+}
@@ -0,0 +1,256 @@
+use std::fmt;
+use std::io::{self, BufWriter, Write};
+use std::ops::Range;
+use std::path::Path;
+use std::sync::Arc;
+use tantivy::directory::error::{DeleteError, LockError, OpenReadError, OpenWriteError};
+use tantivy::directory::{
+    AntiCallToken, Directory, DirectoryLock, FileHandle, Lock, MmapDirectory, OwnedBytes,
+    TerminatingWrite, WatchCallback, WatchHandle, WritePtr,
+};
+use tantivy::HasLen;
+
+use crate::crypto::{self, Cipher, CHUNK_SIZE, ENCRYPTED_CHUNK_SIZE, HEADER_LEN};
+
+// A tantivy directory that encrypts every file it writes, on top of an MmapDirectory.
+// Reads decrypt only the chunks covering the requested range, so search does not need
+// to decrypt whole segment files. Files written before encryption was enabled are
+// read as they are.
+#[derive(Clone)]
+pub struct EncryptedDirectory {
+    inner: MmapDirectory,
+    cipher: &'static Cipher,
+}
+
+impl EncryptedDirectory {
+    pub fn open(path: &str, cipher: &'static Cipher) -> Self {
+        EncryptedDirectory {
+            inner: MmapDirectory::open(path).unwrap(),
+            cipher,
+        }
+    }
+}
+
+impl fmt::Debug for EncryptedDirectory {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "EncryptedDirectory({:?})", self.inner)
+    }
+}
+
+impl Directory for EncryptedDirectory {
+    fn get_file_handle(&self, path: &Path) -> Result<Arc<dyn FileHandle>, OpenReadError> {
+        let inner = self.inner.get_file_handle(path)?;
+        let header = inner
+            .read_bytes(0..HEADER_LEN.min(inner.len()))
+            .map_err(|e| OpenReadError::wrap_io_error(e, path.to_path_buf()))?;
+        if !crypto::is_encrypted(header.as_slice()) {
+            return Ok(inner);
+        }
+        self.cipher
+            .check_header(header.as_slice())
+            .map_err(|e| OpenReadError::wrap_io_error(e, path.to_path_buf()))?;
+        Ok(Arc::new(DecryptingFileHandle {
+            len: crypto::plaintext_len(inner.len()),
+            header: header.as_slice().to_vec(),
+            inner,
+            cipher: self.cipher,
+        }))
+    }
+
+    fn delete(&self, path: &Path) -> Result<(), DeleteError> {
+        self.inner.delete(path)
+    }
+
+    fn exists(&self, path: &Path) -> Result<bool, OpenReadError> {
+        self.inner.exists(path)
+    }
+
+    fn open_write(&self, path: &Path) -> Result<WritePtr, OpenWriteError> {
+        let inner = self.inner.open_write(path)?;
+        let mut writer = EncryptingWriter {
+            inner: Some(inner),
+            cipher: self.cipher,
+            header: self.cipher.header(),
+            chunk: Vec::with_capacity(CHUNK_SIZE),
+            index: 0,
+        };
+        let header = writer.header;
+        writer
+            .inner
+            .as_mut()
+            .unwrap()
+            .write_all(&header)
+            .map_err(|e| OpenWriteError::wrap_io_error(e, path.to_path_buf()))?;
+        Ok(BufWriter::new(Box::new(writer)))
+    }
+
+    fn atomic_read(&self, path: &Path) -> Result<Vec<u8>, OpenReadError> {
+        let data = self.inner.atomic_read(path)?;
+        if !crypto::is_encrypted(&data) {
+            return Ok(data);
+        }
+        self.cipher
+            .decrypt(&data)
+            .map_err(|e| OpenReadError::wrap_io_error(e, path.to_path_buf()))
+    }
+
+    fn atomic_write(&self, path: &Path, data: &[u8]) -> io::Result<()> {
+        self.inner.atomic_write(path, &self.cipher.encrypt(data))
+    }
+
+    fn sync_directory(&self) -> io::Result<()> {
+        self.inner.sync_directory()
+    }
+
+    fn acquire_lock(&self, lock: &Lock) -> Result<DirectoryLock, LockError> {
+        self.inner.acquire_lock(lock)
+    }
+
+    fn watch(&self, watch_callback: WatchCallback) -> tantivy::Result<WatchHandle> {
+        self.inner.watch(watch_callback)
+    }
+}
+
+struct DecryptingFileHandle {
+    inner: Arc<dyn FileHandle>,
+    header: Vec<u8>,
+    cipher: &'static Cipher,
+    len: usize,
+}
+
+impl fmt::Debug for DecryptingFileHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "DecryptingFileHandle({:?})", self.inner)
+    }
+}
+
+impl HasLen for DecryptingFileHandle {
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+impl FileHandle for DecryptingFileHandle {
+    fn read_bytes(&self, range: Range<usize>) -> io::Result<OwnedBytes> {
+        if range.is_empty() {
+            return Ok(OwnedBytes::empty());
+        }
+        let first = range.start / CHUNK_SIZE;
+        let last = (range.end - 1) / CHUNK_SIZE;
+        let chunk_count = (self.len + CHUNK_SIZE - 1) / CHUNK_SIZE;
+        let mut plaintext = Vec::with_capacity((last - first + 1) * CHUNK_SIZE);
+        for index in first..=last {
+            let start = HEADER_LEN + index * ENCRYPTED_CHUNK_SIZE;
+            let end = (start + ENCRYPTED_CHUNK_SIZE).min(self.inner.len());
+            let encrypted = self.inner.read_bytes(start..end)?;
+            plaintext.extend(self.cipher.decrypt_chunk(
+                &self.header,
+                index as u32,
+                index + 1 >= chunk_count,
+                encrypted.as_slice(),
+            )?);
+        }
+        let offset = first * CHUNK_SIZE;
+        plaintext.truncate(range.end - offset);
+        plaintext.drain(..range.start - offset);
+        Ok(OwnedBytes::new(plaintext))
+    }
+}
+
+// Encrypts everything written to it in chunks. A full chunk is only written once more
+// data follows, since the last chunk has to be marked as such.
+struct EncryptingWriter {
+    inner: Option<WritePtr>,
+    cipher: &'static Cipher,
+    header: [u8; HEADER_LEN],
+    chunk: Vec<u8>,
+    index: u32,
+}
+
+impl EncryptingWriter {
+    fn write_chunk(&mut self, last: bool) -> io::Result<()> {
+        let encrypted = self
+            .cipher
+            .encrypt_chunk(&self.header, self.index, last, &self.chunk);
+        self.inner.as_mut().unwrap().write_all(&encrypted)?;
+        self.chunk.clear();
+        self.index += 1;
+        Ok(())
+    }
+}
+
+impl Write for EncryptingWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.chunk.len() == CHUNK_SIZE {
+            self.write_chunk(false)?;
+        }
+        let n = buf.len().min(CHUNK_SIZE - self.chunk.len());
+        self.chunk.extend_from_slice(&buf[..n]);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        // The current chunk can only be written once it is complete
+        self.inner.as_mut().unwrap().flush()
+    }
+}
+
+impl TerminatingWrite for EncryptingWriter {
+    fn terminate_ref(&mut self, _: AntiCallToken) -> io::Result<()> {
+        self.write_chunk(true)?;
+        self.inner.take().unwrap().terminate()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::crypto::KeyMaterial;
+
+    #[test]
+    fn reads_ranges() {
+        let dir = tempfile::tempdir().unwrap();
+        let cipher = Box::leak(Box::new(KeyMaterial::generate().cipher()));
+        let directory = EncryptedDirectory::open(dir.path().to_str().unwrap(), cipher);
+        let data: Vec<u8> = (0..2 * CHUNK_SIZE + 100).map(|i| (i % 251) as u8).collect();
+        let path = Path::new("segment");
+        let mut writer = directory.open_write(path).unwrap();
+        // Written in pieces that don't line up with the chunks
+        for piece in data.chunks(1000) {
+            writer.write_all(piece).unwrap();
+        }
+        writer.terminate().unwrap();
+        assert!(crypto::is_encrypted(
+            &std::fs::read(dir.path().join(path)).unwrap()
+        ));
+
+        let handle = directory.get_file_handle(path).unwrap();
+        assert_eq!(handle.len(), data.len());
+        for range in [
+            0..0,
+            0..10,
+            CHUNK_SIZE - 5..CHUNK_SIZE + 5,
+            CHUNK_SIZE..2 * CHUNK_SIZE,
+            10..data.len(),
+            data.len() - 1..data.len(),
+        ] {
+            let bytes = handle.read_bytes(range.clone()).unwrap();
+            assert_eq!(bytes.as_slice(), &data[range]);
+        }
+    }
+
+    #[test]
+    fn reads_plaintext_files() {
+        let dir = tempfile::tempdir().unwrap();
+        std::fs::write(dir.path().join("meta.json"), b"{}").unwrap();
+        let cipher = Box::leak(Box::new(KeyMaterial::generate().cipher()));
+        let directory = EncryptedDirectory::open(dir.path().to_str().unwrap(), cipher);
+        assert_eq!(
+            directory.atomic_read(Path::new("meta.json")).unwrap(),
+            b"{}"
+        );
+    }
+}
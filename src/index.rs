@@ -0,0 +1,310 @@
+use futures::stream::{self, StreamExt};
+use indicatif::ProgressBar;
+use prost::Message;
+use std::collections::HashSet;
+use std::path::Path;
+use std::sync::Arc;
+use tantivy::directory::{Directory, MmapDirectory};
+use tantivy::schema::*;
+use tantivy::{Document, Index, IndexWriter, SegmentId};
+use tokio::sync::RwLock;
+use tracing::{error, info, info_span, warn};
+
+use crate::api::{OcrWords, ScreenRecord};
+use crate::config::Config;
+use crate::crypto;
+use crate::dhash::image_dhash;
+use crate::encrypted_directory::EncryptedDirectory;
+use crate::language;
+use crate::metrics::metrics;
+use crate::ocr::{OcrEngine, OcrResult};
+use crate::storage::{
+    from_timestamp, read_file, read_sidecar, screen_uid, sidecar_path, stored_screens,
+    to_timestamp, write_sidecar, StoredScreen,
+};
+use crate::vectors::VectorStore;
+
+// Add the OCR output of a screen to its document. The text is additionally indexed in
+// the field of its detected language, so queries match stemmed forms of words.
+pub fn add_ocr_fields(doc: &mut Document, schema: &Schema, config: &Config, ocr: OcrResult) {
+    doc.add_text(schema.get_field("text").unwrap(), &ocr.text);
+    if let Some(lang) = language::index_language(config, &ocr.text) {
+        doc.add_text(schema.get_field("lang").unwrap(), lang);
+        doc.add_text(
+            schema.get_field(&language::text_field_name(lang)).unwrap(),
+            &ocr.text,
+        );
+    }
+    doc.add_bytes(
+        schema.get_field("words").unwrap(),
+        OcrWords { words: ocr.words }.encode_to_vec(),
+    );
+}
+
+// Add the capture time. The date field has microsecond precision, so the sub-second
+// nanoseconds are stored separately to keep the exact time.
+pub fn add_time_fields(doc: &mut Document, schema: &Schema, datetime: chrono::NaiveDateTime) {
+    doc.add_date(
+        schema.get_field("date").unwrap(),
+        tantivy::DateTime::from_timestamp_micros(datetime.timestamp_micros()),
+    );
+    doc.add_u64(
+        schema.get_field("nanos").unwrap(),
+        datetime.timestamp_subsec_nanos() as u64,
+    );
+}
+
+// The capture time of a document
+pub fn doc_datetime(schema: &Schema, doc: &Document) -> chrono::NaiveDateTime {
+    let date = doc
+        .get_first(schema.get_field("date").unwrap())
+        .unwrap()
+        .as_date()
+        .unwrap();
+    let nanos = doc
+        .get_first(schema.get_field("nanos").unwrap())
+        .and_then(|value| value.as_u64())
+        .unwrap_or(0);
+    chrono::NaiveDateTime::from_timestamp_opt(date.into_timestamp_secs(), nanos as u32).unwrap()
+}
+
+// Build the document of a screen from its stored OCR output and metadata
+pub fn record_document(config: &Config, schema: &Schema, record: ScreenRecord) -> Document {
+    let datetime = from_timestamp(&record.time.unwrap_or_default()).unwrap();
+    let mut doc = Document::default();
+    doc.add_u64(
+        schema.get_field("id").unwrap(),
+        screen_uid(datetime, record.screen_id),
+    );
+    add_ocr_fields(
+        &mut doc,
+        schema,
+        config,
+        OcrResult {
+            text: record.text,
+            words: record.words,
+        },
+    );
+    add_time_fields(&mut doc, schema, datetime);
+    doc.add_u64(
+        schema.get_field("screen_id").unwrap(),
+        record.screen_id as u64,
+    );
+    if !record.dhash.is_empty() {
+        doc.add_bytes(schema.get_field("dhash").unwrap(), record.dhash);
+    }
+    doc
+}
+
+// Build the document of a stored screen. The OCR output comes from its sidecar if there
+// is one; otherwise the image is OCRed and the sidecar written for next time. Sidecars
+// without an image hash get one.
+fn screen_document(
+    config: &Config,
+    ocr: &dyn OcrEngine,
+    schema: &Schema,
+    screen: &StoredScreen,
+) -> Document {
+    let record = match read_sidecar(&screen.path) {
+        Some(record) if record.dhash.is_empty() => {
+            let record = ScreenRecord {
+                dhash: stored_dhash(&screen.path),
+                ..record
+            };
+            if let Err(e) = write_sidecar(&screen.path, &record) {
+                warn!(path = %screen.path.display(), "Could not write OCR sidecar: {}", e);
+            }
+            record
+        }
+        Some(record) => record,
+        None => {
+            let result = info_span!("ocr", path = %screen.path.display())
+                .in_scope(|| ocr.recognize_path(&screen.path));
+            let record = ScreenRecord {
+                time: Some(to_timestamp(screen.datetime)),
+                screen_id: screen.screen_id,
+                text: result.text,
+                words: result.words,
+                dhash: stored_dhash(&screen.path),
+            };
+            if let Err(e) = write_sidecar(&screen.path, &record) {
+                warn!(path = %screen.path.display(), "Could not write OCR sidecar: {}", e);
+            }
+            record
+        }
+    };
+    record_document(config, schema, record)
+}
+
+// Packed image hash of a stored screen, empty if the image can't be read
+pub fn stored_dhash(path: &Path) -> Vec<u8> {
+    read_file(path)
+        .ok()
+        .and_then(|image| image_dhash(&image))
+        .unwrap_or_default()
+}
+
+// Delete screens from the index, the vector store and disk. They are removed from the
+// index first, so search never returns a screen without an image. Returns the number of
+// bytes freed.
+//
+// Deleting a document only marks it as deleted; its text stays in the segment files
+// until the segment is merged. So the segments with deleted documents are merged right
+// away, and the files of the old segments removed. Uploads only wait for the commit,
+// not for the merge.
+pub async fn delete_screens(
+    index: &Index,
+    writer_arc: &RwLock<IndexWriter>,
+    schema: &Schema,
+    vectors: Option<&VectorStore>,
+    screens: &[StoredScreen],
+) -> u64 {
+    let merge = {
+        let mut writer = writer_arc.write().await;
+        let id_field = schema.get_field("id").unwrap();
+        for screen in screens {
+            writer.delete_term(Term::from_field_u64(id_field, screen.id));
+        }
+        {
+            let _timer = metrics().commit_duration.start_timer();
+            writer.commit().unwrap();
+        }
+        let segment_ids: Vec<SegmentId> = index
+            .searchable_segment_metas()
+            .unwrap()
+            .into_iter()
+            .filter(|meta| meta.has_deletes())
+            .map(|meta| meta.id())
+            .collect();
+        (!segment_ids.is_empty()).then(|| writer.merge(&segment_ids))
+    };
+    if let Some(merge) = merge {
+        if let Err(e) = merge.await {
+            error!("Could not merge segments with deleted screens: {}", e);
+        }
+    }
+    // The old segments are only unused once the merge is done
+    let garbage_collection = writer_arc.read().await.garbage_collect_files();
+    if let Err(e) = garbage_collection.await {
+        error!("Could not remove unused index files: {}", e);
+    }
+    if let Some(vectors) = vectors {
+        let ids: Vec<u64> = screens.iter().map(|screen| screen.id).collect();
+        if let Err(e) = vectors.remove(&ids) {
+            error!("Could not remove the text embeddings: {}", e);
+        }
+    }
+    let mut bytes = 0;
+    for screen in screens {
+        match std::fs::remove_file(&screen.path) {
+            Ok(_) => bytes += screen.size,
+            Err(e) => warn!(path = %screen.path.display(), "Could not delete: {}", e),
+        }
+        // Screens stored before sidecars were written have none
+        let _ = std::fs::remove_file(sidecar_path(&screen.path));
+    }
+    bytes
+}
+
+// Ids of all screens in the index
+pub fn indexed_ids(index: &Index, schema: &Schema) -> HashSet<u64> {
+    let id_field = schema.get_field("id").unwrap();
+    let searcher = index.reader().unwrap().searcher();
+    let mut ids = HashSet::new();
+    for segment_reader in searcher.segment_readers() {
+        let id_values = segment_reader.fast_fields().u64(id_field).unwrap();
+        for doc in segment_reader.doc_ids_alive() {
+            ids.insert(id_values.get_val(doc as u64));
+        }
+    }
+    ids
+}
+
+// Index every stored screen that is not in the index yet. Up to
+// config.reindex_concurrency images are OCRed at a time, and the index is committed
+// every config.reindex_checkpoint images, so an interrupted rebuild continues where it
+// left off when run again.
+pub async fn rebuild_index(
+    config: &Config,
+    ocr: Arc<dyn OcrEngine>,
+    index: &Index,
+    schema: &Schema,
+) {
+    let indexed = indexed_ids(index, schema);
+    let pending: Vec<StoredScreen> = stored_screens()
+        .into_iter()
+        .filter(|screen| !indexed.contains(&screen.id))
+        .collect();
+    info!(
+        indexed = indexed.len(),
+        pending = pending.len(),
+        "Rebuilding index"
+    );
+
+    let mut writer = index.writer(50_000_000).unwrap();
+    let pb = ProgressBar::new(pending.len() as u64);
+    let mut docs = stream::iter(pending)
+        .map(|screen| {
+            let config = config.clone();
+            let ocr = Arc::clone(&ocr);
+            let schema = schema.clone();
+            tokio::task::spawn_blocking(move || screen_document(&config, &*ocr, &schema, &screen))
+        })
+        .buffer_unordered(config.reindex_concurrency.max(1));
+    let mut count = 0;
+    let mut uncommitted = 0;
+    while let Some(result) = docs.next().await {
+        pb.inc(1);
+        match result {
+            Ok(doc) => {
+                writer.add_document(doc).unwrap();
+                count += 1;
+                uncommitted += 1;
+            }
+            // The task panicked, e.g. on an unreadable image. Skip it.
+            Err(e) => error!("Could not index an image: {}", e),
+        }
+        if uncommitted >= config.reindex_checkpoint {
+            writer.commit().unwrap();
+            uncommitted = 0;
+        }
+    }
+    writer.commit().unwrap();
+    pb.finish();
+    info!(count, "Indexed images");
+}
+
+pub const INDEX_PATH: &str = "index/";
+
+// The directory of an index, encrypted if encryption at rest is enabled
+pub fn open_directory(path: &str) -> Box<dyn Directory> {
+    match crypto::cipher() {
+        Some(cipher) => Box::new(EncryptedDirectory::open(path, cipher)),
+        None => Box::new(MmapDirectory::open(path).unwrap()),
+    }
+}
+
+pub fn make_schema() -> (Schema, Index) {
+    if !std::path::Path::new(INDEX_PATH).exists() {
+        std::fs::create_dir(INDEX_PATH).unwrap();
+    }
+    let mut schema_builder = SchemaBuilder::default();
+    let _id = schema_builder.add_u64_field("id", INDEXED | STORED | FAST);
+    let date_options =
+        DateOptions::from(INDEXED | STORED | FAST).set_precision(DatePrecision::Microseconds);
+    let _date = schema_builder.add_date_field("date", date_options);
+    let _nanos = schema_builder.add_u64_field("nanos", STORED);
+    let _text = schema_builder.add_text_field("text", TEXT | STORED);
+    let _screen_id = schema_builder.add_u64_field("screen_id", INDEXED | STORED | FAST);
+    let _words = schema_builder.add_bytes_field("words", STORED);
+    let _lang = schema_builder.add_text_field("lang", STRING | STORED);
+    let _dhash = schema_builder.add_bytes_field("dhash", STORED | FAST);
+    language::add_text_fields(&mut schema_builder);
+    let schema = schema_builder.build();
+
+    // Create or open the tantivy index
+    let index = Index::open_or_create(open_directory(INDEX_PATH), schema.clone()).unwrap();
+    language::register_tokenizers(&index);
+
+    (schema, index)
+}
@@ -0,0 +1,111 @@
+use prost::Message;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::Mutex;
+use tantivy::schema::{Schema, Term};
+use tantivy::IndexWriter;
+use tokio::sync::RwLock;
+use tracing::error;
+
+use crate::api::ScreenRecord;
+use crate::config::Config;
+use crate::index::record_document;
+use crate::metrics::metrics;
+use crate::storage::{
+    datetime_to_screen_path, decrypt_stored, encrypt_if_enabled, frame_entry, from_timestamp,
+    read_entries, screen_uid,
+};
+
+// Write-ahead log of screens added to the index since the last commit. Every upload is
+// appended (and synced) before it is acknowledged, and the journal is cleared after each
+// commit, so screens whose images are on disk are never lost from the index when the
+// server is killed. Entries are a u32 (LE) length followed by a ScreenRecord, encrypted
+// if encryption at rest is enabled.
+pub const JOURNAL_PATH: &str = "ingest.journal";
+
+pub struct Journal {
+    file: Mutex<File>,
+}
+
+impl Journal {
+    pub fn open(path: &str) -> io::Result<Self> {
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Journal {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn append(&self, record: &ScreenRecord) -> io::Result<()> {
+        let entry = frame_entry(&encrypt_if_enabled(&record.encode_to_vec()));
+        let mut file = self.file.lock().unwrap();
+        file.write_all(&entry)?;
+        file.sync_data()
+    }
+
+    // Commit the index and clear the journal. Uploads hold the writer's read lock while
+    // they add to the journal, so nothing is appended between the commit and the clear.
+    pub async fn commit(&self, writer_arc: &RwLock<IndexWriter>) -> tantivy::Result<()> {
+        let mut writer = writer_arc.write().await;
+        let timer = metrics().commit_duration.start_timer();
+        writer.commit()?;
+        timer.observe_duration();
+        let file = self.file.lock().unwrap();
+        file.set_len(0)?;
+        file.sync_data()?;
+        Ok(())
+    }
+}
+
+// Read the entries of a journal. A truncated last entry (from a crash while it was
+// written) is ignored; that upload was never acknowledged.
+fn read_journal(path: &str) -> io::Result<Vec<ScreenRecord>> {
+    let mut records = vec![];
+    for entry in read_entries(Path::new(path))? {
+        let entry = match decrypt_stored(entry) {
+            Ok(entry) => entry,
+            Err(_) => {
+                error!("Could not decrypt a journal entry, run pms-server reindex");
+                continue;
+            }
+        };
+        match ScreenRecord::decode(entry.as_slice()) {
+            Ok(record) => records.push(record),
+            Err(e) => error!("Invalid journal entry: {}", e),
+        }
+    }
+    Ok(records)
+}
+
+// Add the screens in the journal to the index, e.g. after a crash. Screens that were
+// committed before the journal could be cleared are replaced, not duplicated.
+// Returns the number of screens replayed.
+pub fn replay(
+    config: &Config,
+    schema: &Schema,
+    writer: &mut IndexWriter,
+    path: &str,
+) -> io::Result<usize> {
+    let records = read_journal(path)?;
+    let id_field = schema.get_field("id").unwrap();
+    let mut count = 0;
+    for record in records {
+        let datetime = from_timestamp(&record.time.clone().unwrap_or_default()).unwrap();
+        // Deleted since, e.g. by the retention policy
+        let (dir, fname) = datetime_to_screen_path(datetime, record.screen_id);
+        if !Path::new(&(dir + &fname)).exists() {
+            continue;
+        }
+        writer.delete_term(Term::from_field_u64(
+            id_field,
+            screen_uid(datetime, record.screen_id),
+        ));
+        writer
+            .add_document(record_document(config, schema, record))
+            .unwrap();
+        count += 1;
+    }
+    writer.commit().unwrap();
+    File::create(path)?.sync_data()?;
+    Ok(count)
+}
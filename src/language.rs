@@ -0,0 +1,83 @@
+use tantivy::schema::{IndexRecordOption, SchemaBuilder, TextFieldIndexing, TextOptions};
+use tantivy::tokenizer::{
+    Language, LowerCaser, RemoveLongFilter, SimpleTokenizer, Stemmer, TextAnalyzer,
+};
+use tantivy::Index;
+use whatlang::{Detector, Lang};
+
+use crate::config::Config;
+
+// Languages with a stemmer, as (Tesseract code, tantivy stemmer, whatlang language)
+pub const LANGUAGES: &[(&str, Language, Lang)] = &[
+    ("ara", Language::Arabic, Lang::Ara),
+    ("dan", Language::Danish, Lang::Dan),
+    ("nld", Language::Dutch, Lang::Nld),
+    ("eng", Language::English, Lang::Eng),
+    ("fin", Language::Finnish, Lang::Fin),
+    ("fra", Language::French, Lang::Fra),
+    ("deu", Language::German, Lang::Deu),
+    ("ell", Language::Greek, Lang::Ell),
+    ("hun", Language::Hungarian, Lang::Hun),
+    ("ita", Language::Italian, Lang::Ita),
+    ("nor", Language::Norwegian, Lang::Nob),
+    ("por", Language::Portuguese, Lang::Por),
+    ("ron", Language::Romanian, Lang::Ron),
+    ("rus", Language::Russian, Lang::Rus),
+    ("spa", Language::Spanish, Lang::Spa),
+    ("swe", Language::Swedish, Lang::Swe),
+    ("tam", Language::Tamil, Lang::Tam),
+    ("tur", Language::Turkish, Lang::Tur),
+];
+
+// Name of the (unstored) text field holding text stemmed for the given language
+pub fn text_field_name(code: &str) -> String {
+    format!("text_{}", code)
+}
+
+fn tokenizer_name(code: &str) -> String {
+    format!("stem_{}", code)
+}
+
+// Add one stemmed text field per supported language. All of them are always part of the
+// schema, so changing the configured languages does not require a new index.
+pub fn add_text_fields(schema_builder: &mut SchemaBuilder) {
+    for (code, _, _) in LANGUAGES {
+        let indexing = TextFieldIndexing::default()
+            .set_tokenizer(&tokenizer_name(code))
+            .set_index_option(IndexRecordOption::WithFreqsAndPositions);
+        let options = TextOptions::default().set_indexing_options(indexing);
+        schema_builder.add_text_field(&text_field_name(code), options);
+    }
+}
+
+pub fn register_tokenizers(index: &Index) {
+    for (code, language, _) in LANGUAGES {
+        index.tokenizers().register(
+            &tokenizer_name(code),
+            TextAnalyzer::from(SimpleTokenizer)
+                .filter(RemoveLongFilter::limit(40))
+                .filter(LowerCaser)
+                .filter(Stemmer::new(*language)),
+        );
+    }
+}
+
+// Pick the language to index a screen's text with, among the configured OCR languages.
+// Returns None if none of the configured languages has a stemmer.
+pub fn index_language(config: &Config, text: &str) -> Option<&'static str> {
+    let configured: Vec<&(&str, Language, Lang)> = LANGUAGES
+        .iter()
+        .filter(|(code, _, _)| config.ocr_language_codes().contains(code))
+        .collect();
+    if config.detect_language && configured.len() > 1 {
+        let detector =
+            Detector::with_allowlist(configured.iter().map(|(_, _, lang)| *lang).collect());
+        if let Some(lang) = detector.detect_lang(text) {
+            return configured
+                .iter()
+                .find(|(_, _, l)| *l == lang)
+                .map(|(code, _, _)| *code);
+        }
+    }
+    configured.first().map(|(code, _, _)| *code)
+}
@@ -1,7 +1,32 @@
+#[macro_use]
+extern crate text_io;
+
+pub mod ann;
+pub mod archive;
+pub mod auth;
+pub mod config;
+pub mod crypto;
 pub mod dhash;
+pub mod embed;
+pub mod encrypted_directory;
+pub mod index;
+pub mod journal;
+pub mod language;
+pub mod logging;
+pub mod metrics;
+pub mod migrate;
+pub mod ocr;
+pub mod preprocess;
+pub mod rest;
+pub mod retention;
 #[cfg_attr(target_os = "macos", path = "mac/screenshot.rs")]
 #[cfg_attr(target_os = "linux", path = "linux/screenshot.rs")]
 pub mod screenshot;
+pub mod service;
+pub mod stats;
+pub mod storage;
+pub mod vectors;
+pub mod web;
 
 pub mod api {
     tonic::include_proto!("api");
@@ -0,0 +1,25 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+// Set up logging for both binaries. PMS_LOG sets the verbosity, either a level ("debug")
+// or a filter per module ("info,pms::index=debug"); PMS_LOG_FORMAT=json logs one JSON
+// object per line. When a span (upload, ocr, search, ...) closes, its duration is logged.
+pub fn init() {
+    let filter = EnvFilter::try_from_env("PMS_LOG").unwrap_or_else(|_| EnvFilter::new("info"));
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_span_events(FmtSpan::CLOSE);
+    match std::env::var("PMS_LOG_FORMAT").as_deref() {
+        Ok("json") => builder.json().init(),
+        Ok("text") | Err(_) => builder.init(),
+        Ok(other) => panic!("Invalid value for PMS_LOG_FORMAT: {:?}", other),
+    }
+}
+
+static NEXT_REQUEST_ID: AtomicU64 = AtomicU64::new(1);
+
+// Id to tell the log lines of concurrent requests apart
+pub fn next_request_id() -> u64 {
+    NEXT_REQUEST_ID.fetch_add(1, Ordering::Relaxed)
+}
@@ -0,0 +1,127 @@
+use hyper::header::CONTENT_TYPE;
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server, StatusCode};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, IntCounter, IntGaugeVec, Opts, Registry, TextEncoder,
+};
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::sync::OnceLock;
+
+use crate::index::INDEX_PATH;
+use crate::stats::dir_size;
+
+// Prometheus metrics of the server, served on /metrics
+pub struct Metrics {
+    registry: Registry,
+    pub uploads: IntCounter,
+    pub duplicates: IntCounter,
+    pub ocr_duration: Histogram,
+    pub search_duration: Histogram,
+    pub commit_duration: Histogram,
+    storage_bytes: IntGaugeVec,
+}
+
+static METRICS: OnceLock<Metrics> = OnceLock::new();
+
+pub fn metrics() -> &'static Metrics {
+    METRICS.get_or_init(Metrics::new)
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new();
+        let uploads = IntCounter::new("pms_uploads_total", "Screens uploaded").unwrap();
+        let duplicates = IntCounter::new(
+            "pms_duplicate_uploads_total",
+            "Uploads skipped because the screen did not change",
+        )
+        .unwrap();
+        // OCR takes from well under a second to many seconds for large screens
+        let ocr_duration = Histogram::with_opts(
+            HistogramOpts::new("pms_ocr_duration_seconds", "Time to OCR an uploaded screen")
+                .buckets(vec![0.1, 0.25, 0.5, 1.0, 2.0, 4.0, 8.0, 16.0, 32.0]),
+        )
+        .unwrap();
+        let search_duration = Histogram::with_opts(HistogramOpts::new(
+            "pms_search_duration_seconds",
+            "Time to answer a search",
+        ))
+        .unwrap();
+        let commit_duration = Histogram::with_opts(HistogramOpts::new(
+            "pms_index_commit_duration_seconds",
+            "Time to commit the index",
+        ))
+        .unwrap();
+        let storage_bytes = IntGaugeVec::new(
+            Opts::new("pms_storage_bytes", "Disk usage of the stored data"),
+            &["kind"],
+        )
+        .unwrap();
+        registry.register(Box::new(uploads.clone())).unwrap();
+        registry.register(Box::new(duplicates.clone())).unwrap();
+        registry.register(Box::new(ocr_duration.clone())).unwrap();
+        registry
+            .register(Box::new(search_duration.clone()))
+            .unwrap();
+        registry
+            .register(Box::new(commit_duration.clone()))
+            .unwrap();
+        registry.register(Box::new(storage_bytes.clone())).unwrap();
+        Metrics {
+            registry,
+            uploads,
+            duplicates,
+            ocr_duration,
+            search_duration,
+            commit_duration,
+            storage_bytes,
+        }
+    }
+
+    // The metrics in the Prometheus text format. Disk usage is measured now, as it
+    // also changes when files are removed outside the server.
+    pub async fn render(&self) -> Vec<u8> {
+        let (image_bytes, index_bytes) = tokio::task::spawn_blocking(|| {
+            (
+                dir_size(Path::new("screenshots")),
+                dir_size(Path::new(INDEX_PATH)),
+            )
+        })
+        .await
+        .unwrap();
+        self.storage_bytes
+            .with_label_values(&["images"])
+            .set(image_bytes as i64);
+        self.storage_bytes
+            .with_label_values(&["index"])
+            .set(index_bytes as i64);
+
+        let mut buffer = vec![];
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .unwrap();
+        buffer
+    }
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    if request.uri().path() != "/metrics" {
+        let mut response = Response::new(Body::from("Not found"));
+        *response.status_mut() = StatusCode::NOT_FOUND;
+        return Ok(response);
+    }
+    let mut response = Response::new(Body::from(metrics().render().await));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        TextEncoder::new().format_type().parse().unwrap(),
+    );
+    Ok(response)
+}
+
+// Serve /metrics over plain HTTP
+pub async fn serve_metrics(addr: SocketAddr) -> Result<(), hyper::Error> {
+    let make_service = make_service_fn(|_| async { Ok::<_, Infallible>(service_fn(handle)) });
+    Server::bind(&addr).serve(make_service).await
+}
@@ -0,0 +1,213 @@
+use indicatif::ProgressBar;
+use prost::Message;
+use std::path::Path;
+use std::sync::Arc;
+use tantivy::schema::Schema;
+use tantivy::{DocAddress, Document, Index};
+use tracing::{info, warn};
+
+use crate::api::{OcrWords, ScreenRecord};
+use crate::config::Config;
+use crate::index::{
+    add_ocr_fields, add_time_fields, make_schema, open_directory, rebuild_index, stored_dhash,
+    INDEX_PATH,
+};
+use crate::ocr::{OcrEngine, OcrResult};
+use crate::storage::{read_sidecar, screen_uid, to_timestamp, write_sidecar, StoredScreen};
+
+// Version of the index schema built by make_schema. Bump it whenever the schema changes
+// in a way existing indexes can't be opened with, and handle the old documents in
+// migrate_document.
+//
+// 1: date, text and screen_id (indexes without a version file)
+// 2: ids, sub-second times, word boxes and per-language text fields
+// 3: image hashes (dhash), for finding similar screens
+pub const SCHEMA_VERSION: u32 = 3;
+
+// Records the schema version of index/. It lives outside the index so it is never
+// encrypted or touched by tantivy.
+const VERSION_PATH: &str = "schema_version";
+// The old index is moved here while it is migrated
+const MIGRATING_PATH: &str = "index.migrating/";
+
+fn read_version() -> u32 {
+    match std::fs::read_to_string(VERSION_PATH) {
+        Ok(version) => version
+            .trim()
+            .parse()
+            .unwrap_or_else(|_| panic!("Invalid {}: {:?}", VERSION_PATH, version)),
+        Err(_) => 1,
+    }
+}
+
+fn write_version() -> std::io::Result<()> {
+    std::fs::write(VERSION_PATH, format!("{}\n", SCHEMA_VERSION))
+}
+
+// Make sure index/ exists and has the current schema: build it from the stored screens
+// if it is missing, and migrate it if it was created with an older schema.
+pub async fn prepare_index(
+    config: &Config,
+    ocr: Arc<dyn OcrEngine>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    // An interrupted migration is started over from the old index
+    if Path::new(MIGRATING_PATH).exists() {
+        info!("Resuming interrupted index migration");
+        if Path::new(INDEX_PATH).exists() {
+            std::fs::remove_dir_all(INDEX_PATH)?;
+        }
+        return migrate(config, ocr).await;
+    }
+
+    if !Path::new(INDEX_PATH).exists() {
+        if Path::new("screenshots/").exists() {
+            info!("Rebuilding index");
+            let (schema, index) = make_schema();
+            rebuild_index(config, ocr, &index, &schema).await;
+        }
+        write_version()?;
+        return Ok(());
+    }
+
+    let version = read_version();
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "The index has schema version {}, but this pms-server only supports up to {}",
+            version, SCHEMA_VERSION
+        )
+        .into());
+    }
+    if version < SCHEMA_VERSION {
+        info!(
+            from = version,
+            to = SCHEMA_VERSION,
+            "Migrating index to a new schema version"
+        );
+        std::fs::rename(INDEX_PATH, MIGRATING_PATH)?;
+        migrate(config, ocr).await?;
+    }
+    Ok(())
+}
+
+// Build a new index from the documents of the old one in MIGRATING_PATH. Screens the old
+// index did not have are then indexed from the stored images.
+async fn migrate(
+    config: &Config,
+    ocr: Arc<dyn OcrEngine>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let old_index = Index::open(open_directory(MIGRATING_PATH))?;
+    let old_schema = old_index.schema();
+    let searcher = old_index.reader()?.searcher();
+    let (schema, index) = make_schema();
+    let mut writer = index.writer(50_000_000)?;
+
+    info!(documents = searcher.num_docs(), "Copying documents");
+    let pb = ProgressBar::new(searcher.num_docs());
+    let mut skipped = 0;
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        for doc_id in segment_reader.doc_ids_alive() {
+            let old_doc = searcher.doc(DocAddress::new(segment_ord as u32, doc_id))?;
+            match migrate_document(config, &old_schema, &old_doc, &schema) {
+                Some(doc) => {
+                    writer.add_document(doc)?;
+                }
+                None => skipped += 1,
+            }
+            pb.inc(1);
+        }
+    }
+    writer.commit()?;
+    pb.finish();
+    if skipped > 0 {
+        warn!(skipped, "Skipped documents without a time or screen");
+    }
+    // The writer has to be released before rebuild_index opens its own
+    drop(writer);
+
+    rebuild_index(config, ocr, &index, &schema).await;
+    write_version()?;
+    std::fs::remove_dir_all(MIGRATING_PATH)?;
+    info!("Migration done");
+    Ok(())
+}
+
+// Convert a document of an older schema into the current one. Values the old document
+// lacks are derived from the ones it has.
+fn migrate_document(
+    config: &Config,
+    old_schema: &Schema,
+    old_doc: &Document,
+    schema: &Schema,
+) -> Option<Document> {
+    let value = |name: &str| {
+        old_schema
+            .get_field(name)
+            .and_then(|field| old_doc.get_first(field))
+    };
+    let date = value("date")?.as_date()?;
+    let nanos = value("nanos").and_then(|v| v.as_u64()).unwrap_or(0);
+    let datetime =
+        chrono::NaiveDateTime::from_timestamp_opt(date.into_timestamp_secs(), nanos as u32)?;
+    let screen_id = value("screen_id")?.as_u64()? as u32;
+    let text = value("text").and_then(|v| v.as_text()).unwrap_or("");
+    let words = value("words")
+        .and_then(|v| v.as_bytes())
+        .map(|bytes| {
+            OcrWords::decode(bytes)
+                .map(|words| words.words)
+                .unwrap_or_default()
+        })
+        .unwrap_or_default();
+
+    // Keep the OCR output and image hash next to the image too, so later rebuilds don't
+    // need to OCR or decode it
+    let screen = StoredScreen::new(datetime, screen_id);
+    let mut dhash = vec![];
+    if screen.path.exists() {
+        let sidecar = read_sidecar(&screen.path);
+        dhash = match &sidecar {
+            Some(record) if !record.dhash.is_empty() => record.dhash.clone(),
+            _ => stored_dhash(&screen.path),
+        };
+        let record = match sidecar {
+            Some(record) if !record.dhash.is_empty() => None,
+            Some(record) => Some(ScreenRecord {
+                dhash: dhash.clone(),
+                ..record
+            }),
+            None => Some(ScreenRecord {
+                time: Some(to_timestamp(datetime)),
+                screen_id,
+                text: text.to_string(),
+                words: words.clone(),
+                dhash: dhash.clone(),
+            }),
+        };
+        if let Some(record) = record {
+            if let Err(e) = write_sidecar(&screen.path, &record) {
+                warn!(path = %screen.path.display(), "Could not write OCR sidecar: {}", e);
+            }
+        }
+    }
+
+    let mut doc = Document::default();
+    doc.add_u64(
+        schema.get_field("id").unwrap(),
+        screen_uid(datetime, screen_id),
+    );
+    add_ocr_fields(
+        &mut doc,
+        schema,
+        config,
+        OcrResult {
+            text: text.to_string(),
+            words,
+        },
+    );
+    add_time_fields(&mut doc, schema, datetime);
+    doc.add_u64(schema.get_field("screen_id").unwrap(), screen_id as u64);
+    if !dhash.is_empty() {
+        doc.add_bytes(schema.get_field("dhash").unwrap(), dhash);
+    }
+    Some(doc)
+}
@@ -0,0 +1,164 @@
+use leptess::LepTess;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::api::WordBox;
+use crate::config::Config;
+use crate::preprocess::{preprocess, PreprocessOptions};
+use crate::storage::read_file;
+
+pub struct OcrResult {
+    pub text: String,
+    pub words: Vec<WordBox>,
+}
+
+// Something that can turn a screenshot into text
+pub trait OcrEngine: Send + Sync {
+    // OCR an encoded (e.g. JPEG) image
+    fn recognize(&self, image: &[u8]) -> OcrResult;
+
+    // OCR a stored image
+    fn recognize_path(&self, path: &Path) -> OcrResult {
+        self.recognize(&read_file(path).unwrap())
+    }
+}
+
+// Create the engine selected by PMS_OCR_ENGINE
+pub fn engine_from_config(config: &Config) -> Arc<dyn OcrEngine> {
+    match config.ocr_engine.as_str() {
+        "tesseract" => Arc::new(TesseractEngine::new(
+            &config.ocr_languages,
+            config.preprocess.clone(),
+        )),
+        "fixture" => Arc::new(FixtureEngine::new(&config.ocr_fixture_text)),
+        other => panic!("Unknown OCR engine: {}", other),
+    }
+}
+
+pub struct TesseractEngine {
+    languages: String,
+    preprocess: PreprocessOptions,
+}
+
+impl TesseractEngine {
+    pub fn new(languages: &str, preprocess: PreprocessOptions) -> Self {
+        TesseractEngine {
+            languages: languages.to_string(),
+            preprocess,
+        }
+    }
+}
+
+impl OcrEngine for TesseractEngine {
+    fn recognize(&self, image: &[u8]) -> OcrResult {
+        let mut tess = LepTess::new(None, &self.languages).unwrap();
+        if !self.preprocess.is_enabled() {
+            tess.set_image_from_mem(image).unwrap();
+            return tesseract_result(&mut tess);
+        }
+
+        let (processed, transform) =
+            preprocess(&image::load_from_memory(image).unwrap(), &self.preprocess);
+        let mut png = std::io::Cursor::new(Vec::new());
+        processed
+            .write_to(&mut png, image::ImageOutputFormat::Png)
+            .unwrap();
+        tess.set_image_from_mem(png.get_ref()).unwrap();
+        let mut result = tesseract_result(&mut tess);
+        // Map the word boxes back onto the original image
+        for word in result.words.iter_mut() {
+            transform.to_original(word);
+        }
+        result
+    }
+}
+
+fn tesseract_result(tess: &mut LepTess) -> OcrResult {
+    let text = tess.get_utf8_text().unwrap();
+    let words = parse_tsv_words(&tess.get_tsv_text(0).unwrap());
+    OcrResult { text, words }
+}
+
+// Extract the word-level rows from Tesseract's TSV output. The columns are
+// level, page, block, paragraph, line, word, left, top, width, height, conf, text
+// and words are the rows with level 5.
+fn parse_tsv_words(tsv: &str) -> Vec<WordBox> {
+    tsv.lines()
+        .filter_map(|line| {
+            let cols: Vec<&str> = line.splitn(12, '\t').collect();
+            if cols.len() < 12 || cols[0] != "5" {
+                return None;
+            }
+            let text = cols[11].trim();
+            if text.is_empty() {
+                return None;
+            }
+            Some(WordBox {
+                text: text.to_string(),
+                left: cols[6].parse().ok()?,
+                top: cols[7].parse().ok()?,
+                width: cols[8].parse().ok()?,
+                height: cols[9].parse().ok()?,
+                confidence: cols[10].parse().ok()?,
+            })
+        })
+        .collect()
+}
+
+// Deterministic engine for tests and development without Tesseract. Stored images
+// are "recognised" as the contents of a sidecar text file next to them (screen.jpg
+// -> screen.jpg.txt) if there is one; everything else as the canned text.
+pub struct FixtureEngine {
+    text: String,
+}
+
+// Size of the fake character cell used to lay out fixture word boxes
+const FIXTURE_CHAR_WIDTH: u32 = 10;
+const FIXTURE_LINE_HEIGHT: u32 = 20;
+
+impl FixtureEngine {
+    pub fn new(text: &str) -> Self {
+        FixtureEngine {
+            text: text.to_string(),
+        }
+    }
+
+    fn result(text: &str) -> OcrResult {
+        let mut words = vec![];
+        for (line_idx, line) in text.lines().enumerate() {
+            let mut column = 0;
+            for word in line.split(' ') {
+                if !word.is_empty() {
+                    words.push(WordBox {
+                        text: word.to_string(),
+                        left: column * FIXTURE_CHAR_WIDTH,
+                        top: line_idx as u32 * FIXTURE_LINE_HEIGHT,
+                        width: word.chars().count() as u32 * FIXTURE_CHAR_WIDTH,
+                        height: FIXTURE_LINE_HEIGHT,
+                        confidence: 100.0,
+                    });
+                }
+                column += word.chars().count() as u32 + 1;
+            }
+        }
+        OcrResult {
+            text: text.to_string(),
+            words,
+        }
+    }
+}
+
+impl OcrEngine for FixtureEngine {
+    fn recognize(&self, _image: &[u8]) -> OcrResult {
+        FixtureEngine::result(&self.text)
+    }
+
+    fn recognize_path(&self, path: &Path) -> OcrResult {
+        let mut sidecar = PathBuf::from(path).into_os_string();
+        sidecar.push(".txt");
+        match std::fs::read_to_string(sidecar) {
+            Ok(text) => FixtureEngine::result(&text),
+            Err(_) => FixtureEngine::result(&self.text),
+        }
+    }
+}
@@ -0,0 +1,357 @@
+use image::imageops::{resize, FilterType};
+use image::{DynamicImage, GrayImage, Luma};
+use std::str::FromStr;
+
+use crate::api::WordBox;
+
+// Preprocessing steps applied to a screenshot before OCR. Every step but grayscale
+// works on a greyscale image, so enabling any step implies grayscale.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PreprocessOptions {
+    pub grayscale: bool,
+    // Invert images with a dark background, e.g. dark-mode IDEs
+    pub invert_dark: bool,
+    // Upscale so small UI fonts get enough pixels per glyph
+    pub upscale: bool,
+    // Adaptive (local mean) thresholding to black and white
+    pub binarize: bool,
+    // Detect and undo small rotations of the text
+    pub deskew: bool,
+}
+
+const UPSCALE_FACTOR: u32 = 2;
+// Side length of the window used for adaptive binarization, and how far below the
+// local mean a pixel must be to count as foreground.
+const BINARIZE_WINDOW: u32 = 31;
+const BINARIZE_THRESHOLD: f32 = 0.15;
+// Deskew searches this range of angles (in degrees) in DESKEW_STEP increments
+const DESKEW_MAX_ANGLE: f32 = 5.0;
+const DESKEW_STEP: f32 = 0.25;
+
+impl PreprocessOptions {
+    pub fn is_enabled(&self) -> bool {
+        *self != PreprocessOptions::default()
+    }
+}
+
+// Parse a comma-separated list of steps, e.g. "grayscale,invert,upscale"
+impl FromStr for PreprocessOptions {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut options = PreprocessOptions::default();
+        for step in s.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            match step {
+                "grayscale" => options.grayscale = true,
+                "invert" => options.invert_dark = true,
+                "upscale" => options.upscale = true,
+                "binarize" => options.binarize = true,
+                "deskew" => options.deskew = true,
+                _ => return Err(format!("Unknown preprocessing step: {}", step)),
+            }
+        }
+        Ok(options)
+    }
+}
+
+// How a processed image relates to the original: it was scaled by `scale`, then rotated
+// by `angle` degrees around its centre
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Transform {
+    pub scale: u32,
+    pub angle: f32,
+    pub center: (f32, f32),
+}
+
+impl Transform {
+    const IDENTITY: Transform = Transform {
+        scale: 1,
+        angle: 0.0,
+        center: (0.0, 0.0),
+    };
+
+    // Map a word box found in the processed image back onto the original image. The box
+    // is rotated back, which makes it the bounding box of the rotated corners, and then
+    // scaled back.
+    pub fn to_original(&self, word: &mut WordBox) {
+        let (sin, cos) = self.angle.to_radians().sin_cos();
+        let (cx, cy) = self.center;
+        let (left, top) = (word.left as f32, word.top as f32);
+        let (right, bottom) = (left + word.width as f32, top + word.height as f32);
+        let (mut min_x, mut min_y, mut max_x, mut max_y) = (f32::MAX, f32::MAX, f32::MIN, f32::MIN);
+        for (x, y) in [(left, top), (right, top), (left, bottom), (right, bottom)] {
+            // The inverse of the mapping in rotate
+            let (dx, dy) = (x - cx, y - cy);
+            let (ox, oy) = (dx * cos + dy * sin + cx, -dx * sin + dy * cos + cy);
+            min_x = min_x.min(ox);
+            min_y = min_y.min(oy);
+            max_x = max_x.max(ox);
+            max_y = max_y.max(oy);
+        }
+        let scale = self.scale as f32;
+        word.left = (min_x.max(0.0) / scale).round() as u32;
+        word.top = (min_y.max(0.0) / scale).round() as u32;
+        word.width = ((max_x - min_x.max(0.0)).max(0.0) / scale).round() as u32;
+        word.height = ((max_y - min_y.max(0.0)).max(0.0) / scale).round() as u32;
+    }
+}
+
+// Run the enabled preprocessing steps. Returns the processed image and how it was
+// transformed, so word boxes can be mapped back to the original image.
+pub fn preprocess(image: &DynamicImage, options: &PreprocessOptions) -> (DynamicImage, Transform) {
+    if !options.is_enabled() {
+        return (image.clone(), Transform::IDENTITY);
+    }
+    let mut gray = image.to_luma8();
+    if options.invert_dark && is_dark(&gray) {
+        image::imageops::invert(&mut gray);
+    }
+    let mut transform = Transform::IDENTITY;
+    if options.upscale {
+        transform.scale = UPSCALE_FACTOR;
+        gray = resize(
+            &gray,
+            gray.width() * UPSCALE_FACTOR,
+            gray.height() * UPSCALE_FACTOR,
+            FilterType::CatmullRom,
+        );
+    }
+    if options.deskew {
+        let angle = estimate_skew(&gray);
+        if angle.abs() >= DESKEW_STEP {
+            gray = rotate(&gray, -angle);
+            transform.angle = -angle;
+            transform.center = (gray.width() as f32 / 2.0, gray.height() as f32 / 2.0);
+        }
+    }
+    if options.binarize {
+        gray = binarize(&gray);
+    }
+    (DynamicImage::ImageLuma8(gray), transform)
+}
+
+fn is_dark(image: &GrayImage) -> bool {
+    let sum: u64 = image.pixels().map(|p| p[0] as u64).sum();
+    let count = (image.width() as u64 * image.height() as u64).max(1);
+    sum / count < 128
+}
+
+// Bradley-Roth adaptive thresholding: a pixel is black if it is sufficiently darker
+// than the mean of the window around it. Window sums come from an integral image.
+fn binarize(image: &GrayImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let w = width as usize;
+    let mut integral = vec![0u64; (w + 1) * (height as usize + 1)];
+    for y in 0..height as usize {
+        let mut row_sum = 0u64;
+        for x in 0..w {
+            row_sum += image.get_pixel(x as u32, y as u32)[0] as u64;
+            integral[(y + 1) * (w + 1) + x + 1] = integral[y * (w + 1) + x + 1] + row_sum;
+        }
+    }
+    let half = BINARIZE_WINDOW / 2;
+    GrayImage::from_fn(width, height, |x, y| {
+        let x0 = x.saturating_sub(half) as usize;
+        let y0 = y.saturating_sub(half) as usize;
+        let x1 = (x + half + 1).min(width) as usize;
+        let y1 = (y + half + 1).min(height) as usize;
+        let area = ((x1 - x0) * (y1 - y0)) as f32;
+        let sum = integral[y1 * (w + 1) + x1] + integral[y0 * (w + 1) + x0]
+            - integral[y0 * (w + 1) + x1]
+            - integral[y1 * (w + 1) + x0];
+        let pixel = image.get_pixel(x, y)[0] as f32;
+        if pixel * area <= sum as f32 * (1.0 - BINARIZE_THRESHOLD) {
+            Luma([0])
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+// Estimate the skew angle in degrees with a projection profile: when the text lines are
+// horizontal, the dark pixel counts per row vary the most.
+fn estimate_skew(image: &GrayImage) -> f32 {
+    // A downscaled copy is plenty for finding the angle
+    let sample_width = image.width().min(1000);
+    let sample_height =
+        (image.height() as u64 * sample_width as u64 / image.width().max(1) as u64).max(1) as u32;
+    let sample = resize(image, sample_width, sample_height, FilterType::Triangle);
+    let dark: Vec<(f32, f32)> = sample
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] < 128)
+        .map(|(x, y, _)| (x as f32, y as f32))
+        .collect();
+    if dark.is_empty() {
+        return 0.0;
+    }
+
+    let steps = (DESKEW_MAX_ANGLE / DESKEW_STEP) as i32;
+    let mut best = (0.0, f64::MIN);
+    for i in -steps..=steps {
+        let angle = i as f32 * DESKEW_STEP;
+        let tan = angle.to_radians().tan();
+        let offset = (sample_width as f32 * tan.abs()).ceil() as usize;
+        let mut rows = vec![0u32; sample_height as usize + 2 * offset + 1];
+        for (x, y) in &dark {
+            let row = (y - x * tan).round() as isize + offset as isize;
+            rows[row as usize] += 1;
+        }
+        let n = rows.len() as f64;
+        let mean = rows.iter().map(|&r| r as f64).sum::<f64>() / n;
+        let variance = rows.iter().map(|&r| (r as f64 - mean).powi(2)).sum::<f64>() / n;
+        if variance > best.1 {
+            best = (angle, variance);
+        }
+    }
+    best.0
+}
+
+// Rotate around the centre by the given angle in degrees, filling uncovered areas white
+fn rotate(image: &GrayImage, angle: f32) -> GrayImage {
+    let (width, height) = image.dimensions();
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    GrayImage::from_fn(width, height, |x, y| {
+        let dx = x as f32 - cx;
+        let dy = y as f32 - cy;
+        let sx = (dx * cos + dy * sin + cx).round();
+        let sy = (-dx * sin + dy * cos + cy).round();
+        if sx >= 0.0 && sy >= 0.0 && (sx as u32) < width && (sy as u32) < height {
+            *image.get_pixel(sx as u32, sy as u32)
+        } else {
+            Luma([255])
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_steps() {
+        let options: PreprocessOptions = "grayscale, invert,upscale".parse().unwrap();
+        assert_eq!(
+            options,
+            PreprocessOptions {
+                grayscale: true,
+                invert_dark: true,
+                upscale: true,
+                ..Default::default()
+            }
+        );
+        assert!(!"".parse::<PreprocessOptions>().unwrap().is_enabled());
+        assert!("grayscale,sharpen".parse::<PreprocessOptions>().is_err());
+    }
+
+    #[test]
+    fn binarize_keeps_dark_text() {
+        let image = GrayImage::from_fn(100, 100, |x, y| {
+            if (40..60).contains(&x) && (45..55).contains(&y) {
+                Luma([60])
+            } else {
+                Luma([200])
+            }
+        });
+        let binarized = binarize(&image);
+        assert_eq!(binarized.get_pixel(50, 50)[0], 0);
+        assert_eq!(binarized.get_pixel(10, 10)[0], 255);
+        assert_eq!(binarized.get_pixel(50, 90)[0], 255);
+    }
+
+    // Dark lines of text rising by the given angle in degrees
+    fn skewed_lines(angle: f32) -> GrayImage {
+        let tan = angle.to_radians().tan();
+        GrayImage::from_fn(400, 300, |x, y| {
+            let row = y as f32 - x as f32 * tan;
+            if row > 20.0 && row < 260.0 && row.rem_euclid(30.0) < 4.0 {
+                Luma([0])
+            } else {
+                Luma([255])
+            }
+        })
+    }
+
+    #[test]
+    fn estimates_and_undoes_skew() {
+        assert_eq!(estimate_skew(&skewed_lines(0.0)), 0.0);
+        let image = skewed_lines(2.0);
+        let angle = estimate_skew(&image);
+        assert!((angle - 2.0).abs() <= DESKEW_STEP, "angle {}", angle);
+        assert!(estimate_skew(&rotate(&image, -angle)).abs() <= DESKEW_STEP);
+    }
+
+    // Where a box of the original image ends up in the processed image, the inverse of
+    // to_original
+    fn to_processed(transform: &Transform, word: &WordBox) -> WordBox {
+        let (sin, cos) = transform.angle.to_radians().sin_cos();
+        let (cx, cy) = transform.center;
+        let scale = transform.scale as f32;
+        let (left, top) = (word.left as f32 * scale, word.top as f32 * scale);
+        let (right, bottom) = (
+            left + word.width as f32 * scale,
+            top + word.height as f32 * scale,
+        );
+        let corners = [(left, top), (right, top), (left, bottom), (right, bottom)]
+            .map(|(x, y)| (x - cx, y - cy))
+            .map(|(dx, dy)| (dx * cos - dy * sin + cx, dx * sin + dy * cos + cy));
+        let min_x = corners.iter().map(|c| c.0).fold(f32::MAX, f32::min);
+        let min_y = corners.iter().map(|c| c.1).fold(f32::MAX, f32::min);
+        let max_x = corners.iter().map(|c| c.0).fold(f32::MIN, f32::max);
+        let max_y = corners.iter().map(|c| c.1).fold(f32::MIN, f32::max);
+        WordBox {
+            left: min_x.round() as u32,
+            top: min_y.round() as u32,
+            width: (max_x - min_x).round() as u32,
+            height: (max_y - min_y).round() as u32,
+            ..word.clone()
+        }
+    }
+
+    fn word(left: u32, top: u32, width: u32, height: u32) -> WordBox {
+        WordBox {
+            text: "invoice".to_string(),
+            left,
+            top,
+            width,
+            height,
+            confidence: 90.0,
+        }
+    }
+
+    #[test]
+    fn maps_scaled_boxes_back() {
+        let transform = Transform {
+            scale: 2,
+            ..Transform::IDENTITY
+        };
+        let mut processed = word(200, 100, 80, 20);
+        transform.to_original(&mut processed);
+        assert_eq!(processed, word(100, 50, 40, 10));
+    }
+
+    #[test]
+    fn maps_rotated_boxes_back() {
+        // Upscaled, then rotated around the centre of the upscaled image
+        let transform = Transform {
+            scale: 2,
+            angle: -2.0,
+            center: (800.0, 600.0),
+        };
+        let original = word(50, 400, 60, 12);
+        let mut processed = to_processed(&transform, &original);
+        // The rotation moves the box by more than the tolerance below
+        assert!(processed.top.abs_diff(original.top * 2) > 10);
+        transform.to_original(&mut processed);
+        // Taking the bounding box of a rotated box twice makes it taller by about
+        // 2 sin(2°) of its width
+        assert!(
+            processed.left.abs_diff(original.left) <= 2
+                && processed.top.abs_diff(original.top) <= 2
+                && processed.width.abs_diff(original.width) <= 2
+                && processed.height.abs_diff(original.height) <= 5,
+            "{:?}",
+            processed
+        );
+    }
+}
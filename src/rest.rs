@@ -0,0 +1,281 @@
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response, StatusCode};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::sync::Arc;
+use tonic::{Code, Status};
+
+use crate::api::find_similar_screens_request::Target;
+use crate::api::pms_service_server::PmsService;
+use crate::api::search_request::Mode;
+use crate::api::search_response::Screen;
+use crate::api::{FindSimilarScreensRequest, GetStatsRequest, ListScreensRequest, SearchRequest};
+use crate::archive::parse_range_bound;
+use crate::auth::{authorize_header, Granted, Scope};
+use crate::config::Config;
+use crate::service::ImplPMSService;
+use crate::storage::{from_timestamp, to_timestamp};
+
+// JSON over HTTP for scripts that cannot speak gRPC-Web, served under /api/ on the API
+// port. The routes call the same ImplPMSService methods as the RPCs, and take the search
+// token in the same authorization header. openapi.json describes them.
+const OPENAPI: &str = include_str!("openapi.json");
+
+#[derive(Clone)]
+pub struct RestApi {
+    config: Config,
+    service: Arc<ImplPMSService>,
+}
+
+impl RestApi {
+    pub fn new(config: Config, service: Arc<ImplPMSService>) -> Self {
+        RestApi { config, service }
+    }
+
+    pub async fn handle(&self, request: Request<Body>) -> Response<Body> {
+        match self.route(request).await {
+            Ok(response) => response,
+            Err(status) => error_response(status),
+        }
+    }
+
+    async fn route(&self, request: Request<Body>) -> Result<Response<Body>, Status> {
+        if request.method() != Method::GET {
+            return Err(Status::unimplemented("Only GET is supported"));
+        }
+        let path = request.uri().path().to_string();
+        if path == "/api/openapi.json" {
+            return Ok(json_response(OPENAPI.into()));
+        }
+
+        let header = match request.headers().get(AUTHORIZATION) {
+            Some(value) => Some(
+                value
+                    .to_str()
+                    .map_err(|_| Status::unauthenticated("Invalid authorization header"))?,
+            ),
+            None => None,
+        };
+        let granted = authorize_header(&self.config, header, Scope::Search)?;
+
+        let params: HashMap<String, String> =
+            form_urlencoded::parse(request.uri().query().unwrap_or("").as_bytes())
+                .into_owned()
+                .collect();
+        let segments: Vec<&str> = path.trim_end_matches('/').split('/').skip(2).collect();
+        match segments.as_slice() {
+            // Results are listed without their images, which are served by /image
+            ["search"] => self.search(&params),
+            ["screens"] => self.list(&params),
+            ["screens", id] => {
+                let screen = self.service.screen(parse_id(id)?, false)?;
+                Ok(json_response(screen_json(&screen).to_string()))
+            }
+            ["screens", id, "image"] => {
+                let screen = self.service.screen(parse_id(id)?, true)?;
+                let mut response = Response::new(Body::from(screen.image));
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, "image/jpeg".parse().unwrap());
+                Ok(response)
+            }
+            ["screens", id, "similar"] => self.similar(id, &params),
+            ["stats"] => self.stats(granted).await,
+            _ => Err(Status::not_found(format!("No route for {}", path))),
+        }
+    }
+
+    fn search(&self, params: &HashMap<String, String>) -> Result<Response<Body>, Status> {
+        let query = params
+            .get("q")
+            .ok_or_else(|| Status::invalid_argument("Missing query parameter q"))?;
+        let mode = match params.get("mode") {
+            Some(mode) => Mode::from_str_name(&mode.to_uppercase())
+                .ok_or_else(|| Status::invalid_argument("Invalid mode"))?,
+            None => Mode::Keyword,
+        };
+        let screens = self.service.search(
+            &SearchRequest {
+                query: query.clone(),
+                start_time: time_param(params, "since", false)?,
+                end_time: time_param(params, "until", true)?,
+                mode: mode as i32,
+            },
+            false,
+        )?;
+        let screens: Vec<Value> = screens.iter().map(screen_json).collect();
+        Ok(json_response(json!({ "screens": screens }).to_string()))
+    }
+
+    fn list(&self, params: &HashMap<String, String>) -> Result<Response<Body>, Status> {
+        let response = self.service.list(
+            &ListScreensRequest {
+                start_time: time_param(params, "since", false)?,
+                end_time: time_param(params, "until", true)?,
+                screen_id: number_param(params, "screen_id")?,
+                page_size: number_param(params, "page_size")?.unwrap_or(0),
+                page_token: params.get("page_token").cloned().unwrap_or_default(),
+            },
+            false,
+        )?;
+        let screens: Vec<Value> = response.screens.iter().map(screen_json).collect();
+        Ok(json_response(
+            json!({
+                "screens": screens,
+                "next_page_token": response.next_page_token,
+            })
+            .to_string(),
+        ))
+    }
+
+    fn similar(
+        &self,
+        id: &str,
+        params: &HashMap<String, String>,
+    ) -> Result<Response<Body>, Status> {
+        let response = self.service.similar(
+            FindSimilarScreensRequest {
+                target: Some(Target::Id(parse_id(id)?)),
+                limit: number_param(params, "limit")?.unwrap_or(0),
+                max_distance: number_param(params, "max_distance")?,
+            },
+            false,
+        )?;
+        let matches: Vec<Value> = response
+            .matches
+            .iter()
+            .filter_map(|m| {
+                let mut screen = screen_json(m.screen.as_ref()?);
+                screen["distance"] = m.distance.into();
+                Some(screen)
+            })
+            .collect();
+        Ok(json_response(json!({ "screens": matches }).to_string()))
+    }
+
+    async fn stats(&self, granted: Granted) -> Result<Response<Body>, Status> {
+        let stats = self
+            .service
+            .get_stats(granted.request(GetStatsRequest {}))
+            .await?
+            .into_inner();
+        let days: Vec<Value> = stats
+            .days
+            .iter()
+            .map(|day| json!({ "day": day.day, "count": day.count }))
+            .collect();
+        let screens: Vec<Value> = stats
+            .screens
+            .iter()
+            .map(|screen| json!({ "screen_id": screen.screen_id, "count": screen.count }))
+            .collect();
+        let last_uploads: Vec<Value> = stats
+            .last_uploads
+            .iter()
+            .map(|upload| json!({ "client": upload.client, "time": format_time(&upload.time) }))
+            .collect();
+        Ok(json_response(
+            json!({
+                "total_screens": stats.total_screens,
+                "days": days,
+                "screens": screens,
+                "image_bytes": stats.image_bytes,
+                "index_bytes": stats.index_bytes,
+                "segment_count": stats.segment_count,
+                "ocr_queue_depth": stats.ocr_queue_depth,
+                "uploads": stats.uploads,
+                "duplicates": stats.duplicates,
+                "last_uploads": last_uploads,
+            })
+            .to_string(),
+        ))
+    }
+}
+
+fn time_param(
+    params: &HashMap<String, String>,
+    name: &str,
+    is_end: bool,
+) -> Result<Option<prost_types::Timestamp>, Status> {
+    params
+        .get(name)
+        .map(|value| {
+            let datetime = parse_range_bound(value, is_end).map_err(Status::invalid_argument)?;
+            Ok(to_timestamp(datetime))
+        })
+        .transpose()
+}
+
+fn parse_id(id: &str) -> Result<u64, Status> {
+    id.parse()
+        .map_err(|_| Status::invalid_argument("Invalid screen id"))
+}
+
+fn number_param(params: &HashMap<String, String>, name: &str) -> Result<Option<u32>, Status> {
+    params
+        .get(name)
+        .map(|value| {
+            value
+                .parse()
+                .map_err(|_| Status::invalid_argument(format!("Invalid {}", name)))
+        })
+        .transpose()
+}
+
+// RFC 3339 in UTC
+fn format_time(time: &Option<prost_types::Timestamp>) -> String {
+    from_timestamp(&time.clone().unwrap_or_default())
+        .unwrap()
+        .format("%Y-%m-%dT%H:%M:%S%.fZ")
+        .to_string()
+}
+
+// A screen without its image, which is fetched separately. Ids are strings, as they do
+// not fit in the numbers of many JSON parsers.
+fn screen_json(screen: &Screen) -> Value {
+    let words: Vec<Value> = screen
+        .words
+        .iter()
+        .map(|word| {
+            json!({
+                "text": word.text,
+                "left": word.left,
+                "top": word.top,
+                "width": word.width,
+                "height": word.height,
+                "confidence": word.confidence,
+            })
+        })
+        .collect();
+    json!({
+        "id": screen.id.to_string(),
+        "time": format_time(&screen.time),
+        "screen_id": screen.screen_id,
+        "text": screen.text,
+        "words": words,
+        "image_url": format!("/api/screens/{}/image", screen.id),
+    })
+}
+
+fn json_response(body: String) -> Response<Body> {
+    let mut response = Response::new(Body::from(body));
+    response
+        .headers_mut()
+        .insert(CONTENT_TYPE, "application/json".parse().unwrap());
+    response
+}
+
+fn error_response(status: Status) -> Response<Body> {
+    let code = match status.code() {
+        Code::InvalidArgument => StatusCode::BAD_REQUEST,
+        Code::Unauthenticated => StatusCode::UNAUTHORIZED,
+        Code::PermissionDenied => StatusCode::FORBIDDEN,
+        Code::NotFound => StatusCode::NOT_FOUND,
+        Code::FailedPrecondition => StatusCode::CONFLICT,
+        Code::Unimplemented => StatusCode::METHOD_NOT_ALLOWED,
+        _ => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    let mut response = json_response(json!({ "error": status.message() }).to_string());
+    *response.status_mut() = code;
+    response
+}
@@ -0,0 +1,194 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use tantivy::schema::Schema;
+use tantivy::{Index, IndexWriter};
+use tokio::sync::RwLock;
+use tokio::time;
+use tracing::{debug, info};
+
+use crate::index::delete_screens;
+use crate::storage::{stored_screens, StoredScreen};
+use crate::vectors::VectorStore;
+
+// Limits on how much history is kept. Unset limits are not enforced.
+#[derive(Clone, Debug, Default)]
+pub struct RetentionPolicy {
+    pub max_age_days: Option<u64>,
+    pub max_bytes: Option<u64>,
+    pub max_per_screen: Option<usize>,
+    // How often the limits are enforced
+    pub interval_secs: u64,
+}
+
+impl RetentionPolicy {
+    pub fn is_enabled(&self) -> bool {
+        self.max_age_days.is_some() || self.max_bytes.is_some() || self.max_per_screen.is_some()
+    }
+
+    // Select the screens that violate the policy, oldest first
+    pub fn expired(
+        &self,
+        mut screens: Vec<StoredScreen>,
+        now: chrono::NaiveDateTime,
+    ) -> Vec<StoredScreen> {
+        screens.sort_by_key(|screen| screen.datetime);
+        let mut expired = vec![false; screens.len()];
+
+        if let Some(days) = self.max_age_days {
+            let cutoff = now - chrono::Duration::days(days as i64);
+            for (i, screen) in screens.iter().enumerate() {
+                expired[i] = screen.datetime < cutoff;
+            }
+        }
+
+        if let Some(max) = self.max_per_screen {
+            let mut kept: HashMap<u32, usize> = HashMap::new();
+            for (i, screen) in screens.iter().enumerate().rev() {
+                if expired[i] {
+                    continue;
+                }
+                let count = kept.entry(screen.screen_id).or_default();
+                if *count < max {
+                    *count += 1;
+                } else {
+                    expired[i] = true;
+                }
+            }
+        }
+
+        if let Some(max) = self.max_bytes {
+            let mut total: u64 = screens
+                .iter()
+                .zip(&expired)
+                .filter(|(_, &expired)| !expired)
+                .map(|(screen, _)| screen.size)
+                .sum();
+            for (i, screen) in screens.iter().enumerate() {
+                if total <= max {
+                    break;
+                }
+                if !expired[i] {
+                    expired[i] = true;
+                    total -= screen.size;
+                }
+            }
+        }
+
+        screens
+            .into_iter()
+            .zip(expired)
+            .filter(|(_, expired)| *expired)
+            .map(|(screen, _)| screen)
+            .collect()
+    }
+}
+
+// Periodically delete screens that violate the retention policy
+pub async fn run_retention(
+    policy: RetentionPolicy,
+    index: Index,
+    schema: Schema,
+    writer_arc: Arc<RwLock<IndexWriter>>,
+    vectors: Option<Arc<VectorStore>>,
+) {
+    let mut interval = time::interval(Duration::from_secs(policy.interval_secs));
+    loop {
+        interval.tick().await;
+        let screens = tokio::task::spawn_blocking(stored_screens).await.unwrap();
+        let expired = policy.expired(screens, chrono::Utc::now().naive_utc());
+        if expired.is_empty() {
+            continue;
+        }
+
+        let bytes =
+            delete_screens(&index, &writer_arc, &schema, vectors.as_deref(), &expired).await;
+        for screen in &expired {
+            debug!(path = %screen.path.display(), "Retention: pruned");
+        }
+        info!(screens = expired.len(), bytes, "Retention: pruned screens");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn day(day: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2023, 1, day)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+    }
+
+    fn screen(day_of_month: u32, screen_id: u32, size: u64) -> StoredScreen {
+        StoredScreen {
+            size,
+            ..StoredScreen::new(day(day_of_month), screen_id)
+        }
+    }
+
+    fn expired_days(policy: &RetentionPolicy, screens: Vec<StoredScreen>) -> Vec<(u32, u32)> {
+        policy
+            .expired(screens, day(10))
+            .iter()
+            .map(|screen| (chrono::Datelike::day(&screen.datetime), screen.screen_id))
+            .collect()
+    }
+
+    #[test]
+    fn max_age() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(3),
+            ..Default::default()
+        };
+        let screens = vec![
+            screen(9, 1, 1),
+            screen(2, 1, 1),
+            screen(7, 1, 1),
+            screen(6, 2, 1),
+        ];
+        assert_eq!(expired_days(&policy, screens), vec![(2, 1), (6, 2)]);
+    }
+
+    #[test]
+    fn max_per_screen() {
+        let policy = RetentionPolicy {
+            max_per_screen: Some(2),
+            ..Default::default()
+        };
+        let screens = vec![
+            screen(1, 1, 1),
+            screen(2, 1, 1),
+            screen(3, 1, 1),
+            screen(1, 2, 1),
+            screen(2, 2, 1),
+        ];
+        assert_eq!(expired_days(&policy, screens), vec![(1, 1)]);
+    }
+
+    #[test]
+    fn max_bytes_counts_what_is_kept() {
+        let policy = RetentionPolicy {
+            max_age_days: Some(5),
+            max_bytes: Some(250),
+            ..Default::default()
+        };
+        // The screen from day 1 is too old, which leaves 300 bytes
+        let screens = vec![
+            screen(1, 1, 1000),
+            screen(7, 1, 100),
+            screen(8, 1, 100),
+            screen(9, 1, 100),
+        ];
+        assert_eq!(expired_days(&policy, screens), vec![(1, 1), (7, 1)]);
+    }
+
+    #[test]
+    fn no_limits() {
+        let screens = vec![screen(1, 1, 1000)];
+        assert!(RetentionPolicy::default()
+            .expired(screens, day(10))
+            .is_empty());
+    }
+}
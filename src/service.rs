@@ -0,0 +1,902 @@
+use prost::Message;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs::create_dir_all;
+use std::ops::Bound;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+use std::sync::Arc;
+use tantivy::collector::{Count, DocSetCollector, TopDocs};
+use tantivy::query::{AllQuery, BooleanQuery, Query, RangeQuery, TermQuery, TermSetQuery};
+use tantivy::schema::*;
+use tantivy::{
+    DocAddress, DocId, Document, Index, IndexReader, IndexWriter, Searcher, SegmentReader,
+};
+use tokio::sync::{RwLock, Semaphore};
+use tonic::{Request, Response, Status, Streaming};
+use tracing::{debug, error, field, info, info_span, warn, Instrument, Span};
+
+use crate::api;
+use crate::api::pms_service_server::PmsService;
+use crate::api::{
+    delete_screens_response::DeletedScreen,
+    find_similar_screens_request::Target,
+    find_similar_screens_response::Match as SimilarMatch,
+    get_stats_response::{ClientUpload, DayCount, ScreenCount},
+    search_request::Mode as SearchMode,
+    search_response::Screen as SearchResponseScreen,
+    Ack, DeleteScreensRequest, DeleteScreensResponse, FindSimilarScreensRequest,
+    FindSimilarScreensResponse, GetContextRequest, GetContextResponse, GetScreenRequest,
+    GetStatsRequest, GetStatsResponse, ListScreensRequest, ListScreensResponse, OcrWords,
+    ScreenRecord, SearchRequest, SearchResponse, UploadScreenRequest,
+};
+use crate::auth::{authorize, Scope};
+use crate::config::Config;
+use crate::dhash::{dhash_to_bytes, get_dhash, hamming_distance, image_dhash, IMG_SIZE};
+use crate::index::{delete_screens, doc_datetime, record_document, stored_dhash, INDEX_PATH};
+use crate::journal::Journal;
+use crate::language;
+use crate::logging::next_request_id;
+use crate::metrics::metrics;
+use crate::ocr::OcrEngine;
+use crate::stats::{dir_size, IngestStats};
+use crate::storage::{
+    datetime_to_screen_path, from_timestamp, read_file, screen_uid, to_timestamp, write_new_file,
+    write_sidecar, StoredScreen,
+};
+use crate::vectors::VectorStore;
+
+pub struct ImplPMSService {
+    config: Config,
+    ocr: Arc<dyn OcrEngine>,
+    schema: Schema,
+    index: Index,
+    reader: IndexReader,
+    writer_arc: Arc<RwLock<IndexWriter>>,
+    journal: Arc<Journal>,
+    stats: IngestStats,
+    // Limits how many uploads are OCRed at the same time
+    ocr_slots: Semaphore,
+    hashes: RwLock<HashSet<[bool; IMG_SIZE]>>,
+    // For semantic search, if an embedder is configured
+    vectors: Option<Arc<VectorStore>>,
+}
+
+impl ImplPMSService {
+    pub fn new(
+        config: Config,
+        ocr: Arc<dyn OcrEngine>,
+        schema: Schema,
+        index: Index,
+        writer_arc: Arc<RwLock<IndexWriter>>,
+        journal: Arc<Journal>,
+        vectors: Option<Arc<VectorStore>>,
+    ) -> Self {
+        let reader = index
+            .reader_builder()
+            .reload_policy(tantivy::ReloadPolicy::OnCommit)
+            .try_into()
+            .unwrap();
+        ImplPMSService {
+            ocr_slots: Semaphore::new(config.ocr_concurrency),
+            config,
+            ocr,
+            schema,
+            index,
+            reader,
+            writer_arc,
+            journal,
+            stats: IngestStats::default(),
+            hashes: RwLock::new(HashSet::new()),
+            vectors,
+        }
+    }
+
+    fn searcher(&self) -> Searcher {
+        self.reader.searcher()
+    }
+
+    fn doc_by_id(&self, searcher: &Searcher, id: u64) -> Result<Document, Status> {
+        let query = TermQuery::new(
+            Term::from_field_u64(self.schema.get_field("id").unwrap(), id),
+            IndexRecordOption::Basic,
+        );
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(1))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        match top_docs.first() {
+            Some((_score, doc_address)) => Ok(searcher.doc(*doc_address).unwrap()),
+            None => Err(Status::not_found(format!("No screen with id {}", id))),
+        }
+    }
+
+    fn parse_text_query(&self, query: &str) -> Result<Box<dyn Query>, Status> {
+        // Search both the plain text and the language-specific stemmed text
+        let mut fields = vec![self.schema.get_field("text").unwrap()];
+        for (code, _, _) in language::LANGUAGES {
+            fields.push(
+                self.schema
+                    .get_field(&language::text_field_name(code))
+                    .unwrap(),
+            );
+        }
+        let query_parser = tantivy::query::QueryParser::for_index(&self.index, fields);
+        query_parser
+            .parse_query(query)
+            .map_err(|e| Status::invalid_argument(e.to_string()))
+    }
+
+    // Queries restricting results to a time range and/or a single screen
+    fn filter_queries(
+        &self,
+        start_time: Option<&prost_types::Timestamp>,
+        end_time: Option<&prost_types::Timestamp>,
+        screen_id: Option<u32>,
+    ) -> Vec<Box<dyn Query>> {
+        let mut queries: Vec<Box<dyn Query>> = vec![];
+        if start_time.is_some() || end_time.is_some() {
+            queries.push(Box::new(RangeQuery::new_date_bounds(
+                self.schema.get_field("date").unwrap(),
+                start_time.map_or(Bound::Unbounded, |t| Bound::Included(timestamp_to_date(t))),
+                end_time.map_or(Bound::Unbounded, |t| Bound::Excluded(timestamp_to_date(t))),
+            )));
+        }
+        if let Some(screen_id) = screen_id {
+            queries.push(Box::new(TermQuery::new(
+                Term::from_field_u64(
+                    self.schema.get_field("screen_id").unwrap(),
+                    screen_id as u64,
+                ),
+                IndexRecordOption::Basic,
+            )));
+        }
+        queries
+    }
+
+    // Run a query, ordering the results by time instead of score. Also returns the total
+    // number of matches.
+    fn search_by_time(
+        &self,
+        query: &dyn Query,
+        limit: usize,
+        offset: usize,
+        oldest_first: bool,
+        with_images: bool,
+    ) -> Result<(Vec<SearchResponseScreen>, usize), Status> {
+        let date_field = self.schema.get_field("date").unwrap();
+        let by_time = TopDocs::with_limit(limit).and_offset(offset).custom_score(
+            move |segment_reader: &SegmentReader| {
+                let dates = segment_reader.fast_fields().date(date_field).unwrap();
+                move |doc: DocId| {
+                    let time = dates.get_val(doc as u64).into_timestamp_micros();
+                    if oldest_first {
+                        -time
+                    } else {
+                        time
+                    }
+                }
+            },
+        );
+        let searcher = self.searcher();
+        let count = searcher
+            .search(query, &Count)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        // TopDocs allocates room for offset + limit results up front
+        if offset >= count {
+            return Ok((vec![], count));
+        }
+        let top_docs = searcher
+            .search(query, &by_time)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let screens = top_docs
+            .into_iter()
+            .filter_map(|(_time, doc_address)| {
+                self.screen_from_doc(&searcher.doc(doc_address).unwrap(), with_images)
+            })
+            .collect();
+        Ok((screens, count))
+    }
+
+    // Full-text matches of a search, best first
+    fn keyword_hits(
+        &self,
+        searcher: &Searcher,
+        req: &SearchRequest,
+    ) -> Result<Vec<Document>, Status> {
+        let mut queries = self.filter_queries(req.start_time.as_ref(), req.end_time.as_ref(), None);
+        queries.push(self.parse_text_query(&req.query)?);
+        let query = BooleanQuery::intersection(queries);
+        let top_docs = searcher
+            .search(&query, &TopDocs::with_limit(SEARCH_LIMIT))
+            .map_err(|e| Status::internal(e.to_string()))?;
+        Ok(top_docs
+            .into_iter()
+            .map(|(_score, doc_address)| searcher.doc(doc_address).unwrap())
+            .collect())
+    }
+
+    // Screens closest in meaning to a search, best first
+    fn semantic_hits(
+        &self,
+        searcher: &Searcher,
+        req: &SearchRequest,
+    ) -> Result<Vec<Document>, Status> {
+        let vectors = self.vectors.as_ref().ok_or_else(|| {
+            Status::failed_precondition("Semantic search is disabled, set PMS_EMBEDDER")
+        })?;
+        // Candidates outside the time range are dropped, so look further than the limit
+        let candidates = vectors.search(&req.query, SEARCH_LIMIT * 4);
+        let id_field = self.schema.get_field("id").unwrap();
+        // Look up all candidates in one query. Those deleted since, e.g. by the retention
+        // policy, are not found.
+        let mut queries = self.filter_queries(req.start_time.as_ref(), req.end_time.as_ref(), None);
+        queries.push(Box::new(TermSetQuery::new(
+            candidates
+                .iter()
+                .map(|(id, _similarity)| Term::from_field_u64(id_field, *id)),
+        )));
+        let mut found: HashMap<u64, Document> = searcher
+            .search(&BooleanQuery::intersection(queries), &DocSetCollector)
+            .map_err(|e| Status::internal(e.to_string()))?
+            .into_iter()
+            .map(|doc_address| {
+                let doc = searcher.doc(doc_address).unwrap();
+                let id = doc.get_first(id_field).and_then(|value| value.as_u64());
+                (id.unwrap_or_default(), doc)
+            })
+            .collect();
+        // In the order of similarity
+        Ok(candidates
+            .iter()
+            .filter_map(|(id, _similarity)| found.remove(id))
+            .take(SEARCH_LIMIT)
+            .collect())
+    }
+
+    // The screen of a document. Reading its image can be skipped when it is not needed.
+    // None if the image can't be read, e.g. because the screen was deleted: the reader
+    // only sees a delete some time after it was committed and the files were removed.
+    fn screen_from_doc(
+        &self,
+        retrieved_doc: &Document,
+        with_image: bool,
+    ) -> Option<SearchResponseScreen> {
+        let id = retrieved_doc
+            .get_first(self.schema.get_field("id").unwrap())
+            .unwrap()
+            .as_u64()
+            .unwrap();
+        let text = retrieved_doc
+            .get_first(self.schema.get_field("text").unwrap())
+            .unwrap()
+            .as_text()
+            .unwrap();
+        let datetime = doc_datetime(&self.schema, retrieved_doc);
+        let screen_id = retrieved_doc
+            .get_first(self.schema.get_field("screen_id").unwrap())
+            .unwrap()
+            .as_u64()
+            .unwrap();
+        // Screens indexed before word boxes were stored have no "words" value
+        let words = retrieved_doc
+            .get_first(self.schema.get_field("words").unwrap())
+            .and_then(|value| value.as_bytes())
+            .map(|bytes| OcrWords::decode(bytes).unwrap().words)
+            .unwrap_or_default();
+        let image = if with_image {
+            let (image_path, image_fname) = datetime_to_screen_path(datetime, screen_id as u32);
+            let image_path = image_path + &image_fname;
+            match read_file(Path::new(&image_path)) {
+                Ok(image) => image,
+                Err(e) => {
+                    if e.kind() != std::io::ErrorKind::NotFound {
+                        warn!(path = %image_path, "Could not read image: {}", e);
+                    }
+                    return None;
+                }
+            }
+        } else {
+            vec![]
+        };
+        Some(SearchResponseScreen {
+            id,
+            screen_id: screen_id as u32,
+            image,
+            text: text.to_string(),
+            words,
+            time: Some(to_timestamp(datetime)),
+        })
+    }
+
+    // The implementations of the RPCs returning screens. The JSON API calls them directly
+    // to skip reading the images, which it serves from a separate route, and authorizes
+    // the requests itself.
+
+    pub fn search(
+        &self,
+        req: &SearchRequest,
+        with_images: bool,
+    ) -> Result<Vec<SearchResponseScreen>, Status> {
+        let _timer = metrics().search_duration.start_timer();
+        let span = info_span!("search", request_id = next_request_id(), query = %req.query);
+        let _entered = span.enter();
+        let searcher = self.searcher();
+        let docs = match req.mode() {
+            SearchMode::Keyword => self.keyword_hits(&searcher, req)?,
+            SearchMode::Semantic => self.semantic_hits(&searcher, req)?,
+            SearchMode::Hybrid => merge_hits(
+                &self.schema,
+                self.keyword_hits(&searcher, req)?,
+                self.semantic_hits(&searcher, req)?,
+            ),
+        };
+        info!(results = docs.len(), mode = ?req.mode(), "Searched");
+        Ok(docs
+            .iter()
+            .filter_map(|doc| self.screen_from_doc(doc, with_images))
+            .collect())
+    }
+
+    pub fn screen(&self, id: u64, with_image: bool) -> Result<SearchResponseScreen, Status> {
+        let doc = self.doc_by_id(&self.searcher(), id)?;
+        self.screen_from_doc(&doc, with_image)
+            .ok_or_else(|| Status::not_found(format!("No screen with id {}", id)))
+    }
+
+    pub fn list(
+        &self,
+        req: &ListScreensRequest,
+        with_images: bool,
+    ) -> Result<ListScreensResponse, Status> {
+        const DEFAULT_PAGE_SIZE: usize = 50;
+        let page_size = match req.page_size {
+            0 => DEFAULT_PAGE_SIZE,
+            n => (n as usize).min(SEARCH_LIMIT),
+        };
+        let offset: usize = match req.page_token.as_str() {
+            "" => 0,
+            token => token
+                .parse()
+                .map_err(|_| Status::invalid_argument("Invalid page token"))?,
+        };
+
+        let filters = self.filter_queries(
+            req.start_time.as_ref(),
+            req.end_time.as_ref(),
+            req.screen_id,
+        );
+        let query: Box<dyn Query> = if filters.is_empty() {
+            Box::new(AllQuery)
+        } else {
+            Box::new(BooleanQuery::intersection(filters))
+        };
+
+        let (screens, count) =
+            self.search_by_time(&*query, page_size, offset, true, with_images)?;
+        if offset > count {
+            return Err(Status::invalid_argument("Invalid page token"));
+        }
+        let next_page_token = if offset + screens.len() < count {
+            (offset + screens.len()).to_string()
+        } else {
+            String::new()
+        };
+        Ok(ListScreensResponse {
+            screens,
+            next_page_token,
+        })
+    }
+
+    pub fn similar(
+        &self,
+        req: FindSimilarScreensRequest,
+        with_images: bool,
+    ) -> Result<FindSimilarScreensResponse, Status> {
+        const DEFAULT_LIMIT: usize = 20;
+        let limit = match req.limit {
+            0 => DEFAULT_LIMIT,
+            n => (n as usize).min(SEARCH_LIMIT),
+        };
+        let searcher = self.searcher();
+        let dhash_field = self.schema.get_field("dhash").unwrap();
+
+        let (target_id, target) = match req.target {
+            Some(Target::Id(id)) => {
+                let doc = self.doc_by_id(&searcher, id)?;
+                let dhash = match doc
+                    .get_first(dhash_field)
+                    .and_then(|value| value.as_bytes())
+                {
+                    Some(dhash) => dhash.to_vec(),
+                    // Not hashed yet, e.g. the image could not be read when it was indexed
+                    None => {
+                        let screen_id = doc
+                            .get_first(self.schema.get_field("screen_id").unwrap())
+                            .and_then(|value| value.as_u64())
+                            .unwrap_or_default();
+                        let screen =
+                            StoredScreen::new(doc_datetime(&self.schema, &doc), screen_id as u32);
+                        stored_dhash(&screen.path)
+                    }
+                };
+                (Some(id), dhash)
+            }
+            Some(Target::Image(image)) => (None, image_dhash(&image).unwrap_or_default()),
+            None => return Err(Status::invalid_argument("No screen id or image given")),
+        };
+        if target.is_empty() {
+            return Err(Status::invalid_argument("Could not read the image"));
+        }
+
+        // Compare with the hash of every screen; at 18 bytes each this is fast even for
+        // large libraries
+        let id_field = self.schema.get_field("id").unwrap();
+        let mut matches: Vec<(u32, DocAddress)> = vec![];
+        for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+            let fast_fields = segment_reader.fast_fields();
+            let ids = fast_fields.u64(id_field).unwrap();
+            let hashes = fast_fields.bytes(dhash_field).unwrap();
+            for doc in segment_reader.doc_ids_alive() {
+                let dhash = hashes.get_bytes(doc);
+                if dhash.is_empty() || Some(ids.get_val(doc as u64)) == target_id {
+                    continue;
+                }
+                let distance = hamming_distance(&target, dhash);
+                if req.max_distance.map_or(true, |max| distance <= max) {
+                    matches.push((distance, DocAddress::new(segment_ord as u32, doc)));
+                }
+            }
+        }
+        matches.sort_by_key(|(distance, _)| *distance);
+        matches.truncate(limit);
+        info!(id = ?target_id, matches = matches.len(), "Found similar screens");
+
+        let matches = matches
+            .into_iter()
+            .filter_map(|(distance, doc_address)| {
+                Some(SimilarMatch {
+                    screen: Some(
+                        self.screen_from_doc(&searcher.doc(doc_address).unwrap(), with_images)?,
+                    ),
+                    distance,
+                })
+            })
+            .collect();
+        Ok(FindSimilarScreensResponse { matches })
+    }
+}
+
+// Most results returned by a search
+const SEARCH_LIMIT: usize = 200;
+// Reciprocal rank fusion constant; larger values weigh the top ranks less
+const RRF_K: f32 = 60.0;
+
+// Merge ranked keyword and semantic results by reciprocal rank fusion: each screen scores
+// 1 / (RRF_K + rank) in each list it appears in. This needs no comparable scores, unlike
+// BM25 scores and cosine similarities.
+fn merge_hits(schema: &Schema, keyword: Vec<Document>, semantic: Vec<Document>) -> Vec<Document> {
+    let id_field = schema.get_field("id").unwrap();
+    let mut merged: Vec<(u64, f32, Document)> = vec![];
+    for hits in [keyword, semantic] {
+        for (rank, doc) in hits.into_iter().enumerate() {
+            let id = doc.get_first(id_field).unwrap().as_u64().unwrap();
+            let score = 1.0 / (RRF_K + rank as f32 + 1.0);
+            match merged.iter_mut().find(|(other, _, _)| *other == id) {
+                Some((_, total, _)) => *total += score,
+                None => merged.push((id, score, doc)),
+            }
+        }
+    }
+    merged.sort_by(|a, b| b.1.total_cmp(&a.1));
+    merged
+        .into_iter()
+        .take(SEARCH_LIMIT)
+        .map(|(_, _, doc)| doc)
+        .collect()
+}
+
+fn timestamp_to_date(time: &prost_types::Timestamp) -> tantivy::DateTime {
+    tantivy::DateTime::from_timestamp_micros(time.seconds * 1_000_000 + time.nanos as i64 / 1_000)
+}
+
+#[tonic::async_trait]
+impl PmsService for ImplPMSService {
+    async fn upload_screen(
+        &self,
+        request: Request<Streaming<UploadScreenRequest>>,
+    ) -> Result<Response<Ack>, Status> {
+        authorize(&request, Scope::Upload)?;
+        let client = request
+            .remote_addr()
+            .map_or("unknown".to_string(), |addr| addr.ip().to_string());
+        let span = info_span!(
+            "upload",
+            request_id = next_request_id(),
+            client = %client,
+            screen_id = field::Empty,
+        );
+        let result: Result<(), Status> = async {
+            let mut stream = request.into_inner();
+            let req = stream.message().await?.unwrap();
+            Span::current().record("screen_id", req.screen_id);
+            let time = req.time.unwrap_or_default();
+            let datetime =
+                from_timestamp(&time).ok_or_else(|| Status::invalid_argument("Invalid time"))?;
+            self.stats.record_upload(client, datetime);
+
+            // Hash the image and check if it's already in the index
+            let dyn_image = image::load_from_memory(&req.image).unwrap();
+            let hash = get_dhash(&dyn_image);
+            {
+                let hashes = self.hashes.read().await;
+                if hashes.contains(&hash) {
+                    self.stats.record_duplicate();
+                    debug!("Skipping duplicate screen");
+                    return Ok(());
+                }
+            }
+
+            // OCR the image on the blocking thread pool, so it does not hold up other
+            // requests. Uploads beyond config.ocr_concurrency wait in line.
+            let ocr = {
+                let _queued = self.stats.queue_ocr();
+                let _slot = self.ocr_slots.acquire().await.unwrap();
+                let _timer = metrics().ocr_duration.start_timer();
+                let engine = Arc::clone(&self.ocr);
+                let image = req.image.clone();
+                let span = info_span!("ocr");
+                tokio::task::spawn_blocking(move || span.in_scope(|| engine.recognize(&image)))
+                    .await
+                    .map_err(|e| Status::internal(format!("OCR failed: {}", e)))?
+            };
+
+            // Save the image
+            let (path, fname) = datetime_to_screen_path(datetime, req.screen_id);
+            create_dir_all(&path)?; // Not handled on purpose
+
+            // Never overwrite an existing capture. The name includes the time down to the
+            // nanosecond, so this only happens if the same capture is uploaded twice.
+            let image_path = PathBuf::from(path + &fname);
+            write_new_file(&image_path, &req.image)?;
+            // Keep the OCR output, so rebuilding the index does not need to OCR again
+            let record = ScreenRecord {
+                time: Some(time),
+                screen_id: req.screen_id,
+                text: ocr.text,
+                words: ocr.words,
+                dhash: dhash_to_bytes(&hash),
+            };
+            if let Err(e) = write_sidecar(&image_path, &record) {
+                warn!(path = %image_path.display(), "Could not write OCR sidecar: {}", e);
+            }
+
+            // Index the image. It is journaled before the upload is acknowledged, so it
+            // is not lost if the server stops before the next commit.
+            async {
+                let index_writer = self.writer_arc.read().await;
+                index_writer
+                    .add_document(record_document(&self.config, &self.schema, record.clone()))
+                    .unwrap();
+                self.journal.append(&record)
+            }
+            .instrument(info_span!("index"))
+            .await?;
+            if let Some(vectors) = &self.vectors {
+                let id = screen_uid(datetime, req.screen_id);
+                if let Err(e) = vectors.add(id, &record.text) {
+                    warn!("Could not store the text embedding: {}", e);
+                }
+            }
+
+            {
+                // Add the hash to the set
+                let mut hashes = self.hashes.write().await;
+                hashes.insert(hash);
+            }
+
+            info!(words = record.words.len(), "Indexed screen");
+            Ok(())
+        }
+        .instrument(span.clone())
+        .await;
+        let reply = match result {
+            Ok(_) => Ack { success: true },
+            Err(status) => {
+                span.in_scope(|| error!("Upload failed: {}", status.message()));
+                Ack { success: false }
+            }
+        };
+
+        Ok(Response::new(reply))
+    }
+
+    async fn search_screens(
+        &self,
+        request: Request<SearchRequest>,
+    ) -> Result<Response<SearchResponse>, Status> {
+        authorize(&request, Scope::Search)?;
+        let screens = self.search(&request.into_inner(), true)?;
+        Ok(Response::new(api::SearchResponse { screens }))
+    }
+
+    async fn get_screen(
+        &self,
+        request: Request<GetScreenRequest>,
+    ) -> Result<Response<SearchResponseScreen>, Status> {
+        authorize(&request, Scope::Search)?;
+        Ok(Response::new(self.screen(request.into_inner().id, true)?))
+    }
+
+    async fn list_screens(
+        &self,
+        request: Request<ListScreensRequest>,
+    ) -> Result<Response<ListScreensResponse>, Status> {
+        authorize(&request, Scope::Search)?;
+        Ok(Response::new(self.list(&request.into_inner(), true)?))
+    }
+
+    async fn get_context(
+        &self,
+        request: Request<GetContextRequest>,
+    ) -> Result<Response<GetContextResponse>, Status> {
+        authorize(&request, Scope::Search)?;
+        const DEFAULT_COUNT: usize = 5;
+        let req = request.into_inner();
+        let count = match req.count {
+            0 => DEFAULT_COUNT,
+            n => (n as usize).min(SEARCH_LIMIT),
+        };
+        let (date, screen_id) = match (req.id, req.time) {
+            (Some(id), _) => {
+                let doc = self.doc_by_id(&self.searcher(), id)?;
+                let date = doc
+                    .get_first(self.schema.get_field("date").unwrap())
+                    .unwrap()
+                    .as_date()
+                    .unwrap();
+                let screen_id = doc
+                    .get_first(self.schema.get_field("screen_id").unwrap())
+                    .unwrap()
+                    .as_u64()
+                    .unwrap();
+                (date, screen_id)
+            }
+            (None, Some(time)) => (timestamp_to_date(&time), req.screen_id as u64),
+            (None, None) => return Err(Status::invalid_argument("Missing id or time")),
+        };
+
+        let date_field = self.schema.get_field("date").unwrap();
+        let same_screen = || -> Box<dyn Query> {
+            Box::new(TermQuery::new(
+                Term::from_field_u64(self.schema.get_field("screen_id").unwrap(), screen_id),
+                IndexRecordOption::Basic,
+            ))
+        };
+        let in_range = |lower: Bound<tantivy::DateTime>, upper: Bound<tantivy::DateTime>| {
+            BooleanQuery::intersection(vec![
+                same_screen(),
+                Box::new(RangeQuery::new_date_bounds(date_field, lower, upper)),
+            ])
+        };
+
+        let (mut before, _) = self.search_by_time(
+            &in_range(Bound::Unbounded, Bound::Excluded(date)),
+            count,
+            0,
+            false,
+            true,
+        )?;
+        before.reverse();
+        let (screen, _) = self.search_by_time(
+            &in_range(Bound::Included(date), Bound::Included(date)),
+            1,
+            0,
+            true,
+            true,
+        )?;
+        let (after, _) = self.search_by_time(
+            &in_range(Bound::Excluded(date), Bound::Unbounded),
+            count,
+            0,
+            true,
+            true,
+        )?;
+        Ok(Response::new(GetContextResponse {
+            before,
+            screen: screen.into_iter().next(),
+            after,
+        }))
+    }
+
+    async fn delete_screens(
+        &self,
+        request: Request<DeleteScreensRequest>,
+    ) -> Result<Response<DeleteScreensResponse>, Status> {
+        authorize(&request, Scope::Delete)?;
+        let req = request.into_inner();
+        let mut queries = self.filter_queries(
+            req.start_time.as_ref(),
+            req.end_time.as_ref(),
+            req.screen_id,
+        );
+        if !req.query.is_empty() {
+            queries.push(self.parse_text_query(&req.query)?);
+        }
+        if !req.ids.is_empty() {
+            let id_field = self.schema.get_field("id").unwrap();
+            let by_id: Vec<Box<dyn Query>> = req
+                .ids
+                .iter()
+                .map(|&id| -> Box<dyn Query> {
+                    Box::new(TermQuery::new(
+                        Term::from_field_u64(id_field, id),
+                        IndexRecordOption::Basic,
+                    ))
+                })
+                .collect();
+            queries.push(Box::new(BooleanQuery::union(by_id)));
+        }
+        if queries.is_empty() {
+            return Err(Status::invalid_argument(
+                "Refusing to delete everything, give ids, a time range, screen id or query",
+            ));
+        }
+
+        let searcher = self.searcher();
+        let doc_addresses = searcher
+            .search(&BooleanQuery::intersection(queries), &DocSetCollector)
+            .map_err(|e| Status::internal(e.to_string()))?;
+        let mut screens = vec![];
+        for doc_address in doc_addresses {
+            let doc = searcher.doc(doc_address).unwrap();
+            let screen_id = doc
+                .get_first(self.schema.get_field("screen_id").unwrap())
+                .unwrap()
+                .as_u64()
+                .unwrap();
+            let id = doc
+                .get_first(self.schema.get_field("id").unwrap())
+                .unwrap()
+                .as_u64()
+                .unwrap();
+            screens.push(StoredScreen {
+                id,
+                ..StoredScreen::new(doc_datetime(&self.schema, &doc), screen_id as u32)
+            });
+        }
+        screens.sort_by_key(|screen| screen.datetime);
+
+        if !req.dry_run {
+            let bytes = delete_screens(
+                &self.index,
+                &self.writer_arc,
+                &self.schema,
+                self.vectors.as_deref(),
+                &screens,
+            )
+            .await;
+            info!(screens = screens.len(), bytes, "Deleted screens");
+        }
+        Ok(Response::new(DeleteScreensResponse {
+            screens: screens
+                .iter()
+                .map(|screen| DeletedScreen {
+                    id: screen.id,
+                    screen_id: screen.screen_id,
+                    time: Some(to_timestamp(screen.datetime)),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_stats(
+        &self,
+        request: Request<GetStatsRequest>,
+    ) -> Result<Response<GetStatsResponse>, Status> {
+        authorize(&request, Scope::Search)?;
+        let searcher = self.searcher();
+        let date_field = self.schema.get_field("date").unwrap();
+        let screen_id_field = self.schema.get_field("screen_id").unwrap();
+        let mut days = BTreeMap::new();
+        let mut screens = BTreeMap::new();
+        for segment_reader in searcher.segment_readers() {
+            let fast_fields = segment_reader.fast_fields();
+            let dates = fast_fields.date(date_field).unwrap();
+            let screen_ids = fast_fields.u64(screen_id_field).unwrap();
+            for doc in segment_reader.doc_ids_alive() {
+                let micros = dates.get_val(doc as u64).into_timestamp_micros();
+                let day =
+                    chrono::NaiveDateTime::from_timestamp_opt(micros.div_euclid(1_000_000), 0)
+                        .unwrap()
+                        .date();
+                *days.entry(day).or_insert(0) += 1;
+                *screens
+                    .entry(screen_ids.get_val(doc as u64) as u32)
+                    .or_insert(0) += 1;
+            }
+        }
+
+        let (image_bytes, index_bytes) = tokio::task::spawn_blocking(|| {
+            (
+                dir_size(Path::new("screenshots")),
+                dir_size(Path::new(INDEX_PATH)),
+            )
+        })
+        .await
+        .map_err(|e| Status::internal(e.to_string()))?;
+
+        Ok(Response::new(GetStatsResponse {
+            total_screens: searcher.num_docs(),
+            days: days
+                .into_iter()
+                .map(|(day, count)| DayCount {
+                    day: day.format("%Y-%m-%d").to_string(),
+                    count,
+                })
+                .collect(),
+            screens: screens
+                .into_iter()
+                .map(|(screen_id, count)| ScreenCount { screen_id, count })
+                .collect(),
+            image_bytes,
+            index_bytes,
+            segment_count: searcher.segment_readers().len() as u32,
+            ocr_queue_depth: self.stats.ocr_queue_depth(),
+            uploads: self.stats.uploads.load(Ordering::Relaxed),
+            duplicates: self.stats.duplicates.load(Ordering::Relaxed),
+            last_uploads: self
+                .stats
+                .last_uploads()
+                .into_iter()
+                .map(|(client, time)| ClientUpload {
+                    client,
+                    time: Some(to_timestamp(time)),
+                })
+                .collect(),
+        }))
+    }
+
+    async fn find_similar_screens(
+        &self,
+        request: Request<FindSimilarScreensRequest>,
+    ) -> Result<Response<FindSimilarScreensResponse>, Status> {
+        authorize(&request, Scope::Search)?;
+        Ok(Response::new(self.similar(request.into_inner(), true)?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn docs(schema: &Schema, ids: &[u64]) -> Vec<Document> {
+        let id_field = schema.get_field("id").unwrap();
+        ids.iter()
+            .map(|&id| tantivy::doc!(id_field => id))
+            .collect()
+    }
+
+    #[test]
+    fn merge_hits_by_rank() {
+        let mut builder = SchemaBuilder::default();
+        let id_field = builder.add_u64_field("id", STORED);
+        let schema = builder.build();
+        // 3 is ranked well in both lists, so it beats the first hits of either
+        let merged = merge_hits(&schema, docs(&schema, &[1, 3, 4]), docs(&schema, &[2, 3]));
+        let ids: Vec<u64> = merged
+            .iter()
+            .map(|doc| doc.get_first(id_field).unwrap().as_u64().unwrap())
+            .collect();
+        assert_eq!(ids, [3, 1, 2, 4]);
+    }
+
+    #[test]
+    fn merge_hits_limits_results() {
+        let mut builder = SchemaBuilder::default();
+        builder.add_u64_field("id", STORED);
+        let schema = builder.build();
+        let keyword: Vec<u64> = (0..SEARCH_LIMIT as u64).collect();
+        let semantic: Vec<u64> = (1000..1000 + SEARCH_LIMIT as u64).collect();
+        let merged = merge_hits(&schema, docs(&schema, &keyword), docs(&schema, &semantic));
+        assert_eq!(merged.len(), SEARCH_LIMIT);
+    }
+}
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use std::sync::Mutex;
+use walkdir::WalkDir;
+
+use crate::metrics::metrics;
+
+// Counters of the upload path since the server started, reported by GetStats
+#[derive(Default)]
+pub struct IngestStats {
+    pub uploads: AtomicU64,
+    pub duplicates: AtomicU64,
+    ocr_queue_depth: AtomicU32,
+    // Time of the last upload from each client address
+    last_uploads: Mutex<HashMap<String, chrono::NaiveDateTime>>,
+}
+
+impl IngestStats {
+    pub fn record_upload(&self, client: String, time: chrono::NaiveDateTime) {
+        self.uploads.fetch_add(1, Ordering::Relaxed);
+        metrics().uploads.inc();
+        self.last_uploads.lock().unwrap().insert(client, time);
+    }
+
+    pub fn record_duplicate(&self) {
+        self.duplicates.fetch_add(1, Ordering::Relaxed);
+        metrics().duplicates.inc();
+    }
+
+    // Count an upload as queued for OCR until the returned guard is dropped
+    pub fn queue_ocr(&self) -> OcrQueueGuard {
+        self.ocr_queue_depth.fetch_add(1, Ordering::Relaxed);
+        OcrQueueGuard { stats: self }
+    }
+
+    pub fn ocr_queue_depth(&self) -> u32 {
+        self.ocr_queue_depth.load(Ordering::Relaxed)
+    }
+
+    pub fn last_uploads(&self) -> Vec<(String, chrono::NaiveDateTime)> {
+        let mut uploads: Vec<_> = self
+            .last_uploads
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(client, time)| (client.clone(), *time))
+            .collect();
+        uploads.sort();
+        uploads
+    }
+}
+
+pub struct OcrQueueGuard<'a> {
+    stats: &'a IngestStats,
+}
+
+impl Drop for OcrQueueGuard<'_> {
+    fn drop(&mut self) {
+        self.stats.ocr_queue_depth.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+// Total size of the files in a directory tree
+pub fn dir_size(path: &Path) -> u64 {
+    WalkDir::new(path)
+        .into_iter()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.file_type().is_file())
+        .filter_map(|entry| entry.metadata().ok())
+        .map(|metadata| metadata.len())
+        .sum()
+}
@@ -0,0 +1,274 @@
+use chrono::Datelike;
+use prost::Message;
+use std::borrow::Cow;
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+use walkdir::WalkDir;
+
+use crate::api::ScreenRecord;
+use crate::crypto;
+
+// A screenshot on disk
+pub struct StoredScreen {
+    pub id: u64,
+    pub path: PathBuf,
+    pub datetime: chrono::NaiveDateTime,
+    pub screen_id: u32,
+    pub size: u64,
+}
+
+impl StoredScreen {
+    pub fn new(datetime: chrono::NaiveDateTime, screen_id: u32) -> Self {
+        let (path, fname) = datetime_to_screen_path(datetime, screen_id);
+        let path = PathBuf::from(path + &fname);
+        let size = std::fs::metadata(&path).map_or(0, |m| m.len());
+        StoredScreen {
+            id: screen_uid(datetime, screen_id),
+            path,
+            datetime,
+            screen_id,
+            size,
+        }
+    }
+}
+
+// Stable identifier of a capture. It is derived from the capture time and screen, so a
+// screen keeps its id when the index is rebuilt. (64-bit FNV-1a)
+pub fn screen_uid(datetime: chrono::NaiveDateTime, screen_id: u32) -> u64 {
+    let bytes = datetime
+        .timestamp()
+        .to_le_bytes()
+        .into_iter()
+        .chain(datetime.timestamp_subsec_nanos().to_le_bytes())
+        .chain(screen_id.to_le_bytes());
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in bytes {
+        hash ^= byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+pub fn datetime_to_screen_path(
+    datetime: chrono::NaiveDateTime,
+    screen_id: u32,
+) -> (String, String) {
+    let path = format!(
+        "screenshots/{}/{}/{}/",
+        datetime.year(),
+        datetime.month(),
+        datetime.day()
+    );
+    // Sub-second captures get the nanoseconds in their name, so that captures within the
+    // same second do not overwrite each other
+    let time = if datetime.timestamp_subsec_nanos() == 0 {
+        datetime.format("%H%M%S").to_string()
+    } else {
+        datetime.format("%H%M%S.%9f").to_string()
+    };
+    let fname = format!("{}-{}.jpg", time, screen_id);
+    (path, fname)
+}
+
+// A capture time as sent and stored in protobuf messages
+pub fn to_timestamp(datetime: chrono::NaiveDateTime) -> prost_types::Timestamp {
+    prost_types::Timestamp {
+        seconds: datetime.timestamp(),
+        nanos: datetime.timestamp_subsec_nanos() as i32,
+    }
+}
+
+// The capture time of a protobuf timestamp, None if it is out of range
+pub fn from_timestamp(time: &prost_types::Timestamp) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::from_timestamp_opt(time.seconds, u32::try_from(time.nanos).ok()?)
+}
+
+// Parse the capture time and screen id back out of a path made by datetime_to_screen_path
+pub fn parse_screen_path(path: &str) -> Option<(chrono::NaiveDateTime, u32)> {
+    let parse = || -> Result<(chrono::NaiveDateTime, u32), Box<dyn std::error::Error>> {
+        let year: i32;
+        let month: u32;
+        let day: u32;
+        let time: String;
+        let screen_id: u32;
+        try_scan!(path.bytes() => "screenshots/{}/{}/{}/{}-{}.jpg", year, month, day, time, screen_id);
+        // HHMMSS, optionally followed by .nnnnnnnnn
+        let (hms, nanos) = time.split_once('.').unwrap_or((&time, "0"));
+        let time = chrono::NaiveTime::parse_from_str(hms, "%H%M%S")?
+            + chrono::Duration::nanoseconds(nanos.parse()?);
+        let datetime = chrono::NaiveDate::from_ymd_opt(year, month, day)
+            .ok_or("Invalid date")?
+            .and_time(time);
+        Ok((datetime, screen_id))
+    };
+    parse().ok()
+}
+
+// All screenshots on disk. Unreadable directories and files are logged and skipped.
+pub fn stored_screens() -> Vec<StoredScreen> {
+    let mut screens = vec![];
+    for entry in WalkDir::new("screenshots") {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(e) => {
+                warn!("Could not read stored screens: {}", e);
+                continue;
+            }
+        };
+        if !entry.file_type().is_file() {
+            continue;
+        }
+        let path = entry.path();
+        // Skip sidecars and other files next to the images
+        if path.extension().map_or(true, |ext| ext != "jpg") {
+            continue;
+        }
+        if let Some((datetime, screen_id)) = path.to_str().and_then(parse_screen_path) {
+            let size = match entry.metadata() {
+                Ok(metadata) => metadata.len(),
+                Err(e) => {
+                    warn!(path = %path.display(), "Could not read stored screen: {}", e);
+                    continue;
+                }
+            };
+            screens.push(StoredScreen {
+                id: screen_uid(datetime, screen_id),
+                path: path.to_path_buf(),
+                datetime,
+                screen_id,
+                size,
+            });
+        }
+    }
+    screens
+}
+
+// Read a stored file, decrypting it if it was encrypted
+pub fn read_file(path: &Path) -> io::Result<Vec<u8>> {
+    decrypt_stored(std::fs::read(path)?)
+}
+
+// Stored data as it was written, decrypted if it was encrypted
+pub fn decrypt_stored(data: Vec<u8>) -> io::Result<Vec<u8>> {
+    if !crypto::is_encrypted(&data) {
+        return Ok(data);
+    }
+    match crypto::cipher() {
+        Some(cipher) => cipher.decrypt(&data),
+        None => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "File is encrypted, but no key file is loaded",
+        )),
+    }
+}
+
+// Write a new file, encrypted if encryption at rest is enabled. Never overwrites an
+// existing file.
+pub fn write_new_file(path: &Path, data: &[u8]) -> io::Result<()> {
+    let mut file = OpenOptions::new().write(true).create_new(true).open(path)?;
+    file.write_all(&encrypt_if_enabled(data))
+}
+
+// The data as it is written to disk
+pub fn encrypt_if_enabled(data: &[u8]) -> Cow<[u8]> {
+    match crypto::cipher() {
+        Some(cipher) => Cow::Owned(cipher.encrypt(data)),
+        None => Cow::Borrowed(data),
+    }
+}
+
+// The journal and the vector store are files of entries that are appended one at a
+// time, and encrypted one by one if encryption at rest is enabled. Each entry is a u32
+// (LE) length followed by the (possibly encrypted) entry.
+
+// An entry with its length, to append to an entry file
+pub fn frame_entry(data: &[u8]) -> Vec<u8> {
+    let mut framed = (data.len() as u32).to_le_bytes().to_vec();
+    framed.extend_from_slice(data);
+    framed
+}
+
+// The entries of an entry file as they are stored; none if the file does not exist. A
+// truncated last entry, from a crash while it was written, is left out.
+pub fn read_entries(path: &Path) -> io::Result<Vec<Vec<u8>>> {
+    let data = match std::fs::read(path) {
+        Ok(data) => data,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e),
+    };
+    let mut entries = vec![];
+    let mut rest = data.as_slice();
+    while rest.len() >= 4 {
+        let len = u32::from_le_bytes(rest[..4].try_into().unwrap()) as usize;
+        if rest.len() < 4 + len {
+            break;
+        }
+        entries.push(rest[4..4 + len].to_vec());
+        rest = &rest[4 + len..];
+    }
+    Ok(entries)
+}
+
+// Path of the sidecar holding the OCR output of a screenshot (screen.jpg -> screen.jpg.ocr)
+pub fn sidecar_path(image_path: &Path) -> PathBuf {
+    let mut path = image_path.as_os_str().to_owned();
+    path.push(".ocr");
+    PathBuf::from(path)
+}
+
+// Store the OCR output of a screenshot next to it. The sidecar is written to a temporary
+// file first, so a crash never leaves a truncated one behind.
+pub fn write_sidecar(image_path: &Path, record: &ScreenRecord) -> io::Result<()> {
+    let path = sidecar_path(image_path);
+    let tmp = path.with_extension("ocr.tmp");
+    std::fs::write(&tmp, encrypt_if_enabled(&record.encode_to_vec()))?;
+    std::fs::rename(tmp, path)
+}
+
+// The stored OCR output of a screenshot, if there is a readable sidecar
+pub fn read_sidecar(image_path: &Path) -> Option<ScreenRecord> {
+    let data = read_file(&sidecar_path(image_path)).ok()?;
+    ScreenRecord::decode(data.as_slice()).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn datetime(nanos: u32) -> chrono::NaiveDateTime {
+        chrono::NaiveDate::from_ymd_opt(2023, 1, 31)
+            .unwrap()
+            .and_hms_nano_opt(9, 5, 7, nanos)
+            .unwrap()
+    }
+
+    #[test]
+    fn parses_screen_path() {
+        assert_eq!(
+            parse_screen_path("screenshots/2023/1/31/090507-2.jpg"),
+            Some((datetime(0), 2))
+        );
+        assert_eq!(
+            parse_screen_path("screenshots/2023/1/31/090507.000001500-2.jpg"),
+            Some((datetime(1500), 2))
+        );
+        assert_eq!(parse_screen_path("screenshots/2023/1/31/notes.txt"), None);
+        assert_eq!(
+            parse_screen_path("screenshots/2023/2/30/090507-2.jpg"),
+            None
+        );
+    }
+
+    #[test]
+    fn screen_path_round_trip() {
+        for nanos in [0, 1, 123_456_789] {
+            let (dir, fname) = datetime_to_screen_path(datetime(nanos), 3);
+            assert_eq!(
+                parse_screen_path(&(dir + &fname)),
+                Some((datetime(nanos), 3))
+            );
+        }
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, RwLock};
+use tantivy::schema::Schema;
+use tantivy::{DocAddress, Index};
+use tracing::{error, info};
+
+use crate::ann::AnnIndex;
+use crate::embed::Embedder;
+use crate::index::indexed_ids;
+use crate::storage::{decrypt_stored, encrypt_if_enabled, frame_entry, read_entries};
+
+// The text embeddings of the indexed screens, for semantic search. They are kept in
+// memory in an AnnIndex and appended to VECTORS_PATH as screens are added. Like the
+// journal, the file is a list of entries: a u32 (LE) length followed by the entry,
+// encrypted if encryption at rest is enabled. The first entry names the embedder
+// ("PMSV", u32 dimensions, name), the others are a u64 screen id and its vector (f32s).
+pub const VECTORS_PATH: &str = "vectors.bin";
+const MAGIC: &[u8] = b"PMSV";
+
+pub struct VectorStore {
+    embedder: Arc<dyn Embedder>,
+    index: RwLock<AnnIndex>,
+    file: Mutex<File>,
+}
+
+fn write_entry(file: &mut File, entry: &[u8]) -> io::Result<()> {
+    file.write_all(&frame_entry(&encrypt_if_enabled(entry)))
+}
+
+// The decrypted entries of the vector file
+fn stored_entries() -> io::Result<Vec<Vec<u8>>> {
+    let mut entries = vec![];
+    for entry in read_entries(Path::new(VECTORS_PATH))? {
+        match decrypt_stored(entry) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => error!("Could not decrypt a stored vector"),
+        }
+    }
+    Ok(entries)
+}
+
+fn header(embedder: &dyn Embedder) -> Vec<u8> {
+    let mut entry = MAGIC.to_vec();
+    entry.extend((embedder.dimensions() as u32).to_le_bytes());
+    entry.extend(embedder.name().as_bytes());
+    entry
+}
+
+fn vector_entry(id: u64, vector: &[f32]) -> Vec<u8> {
+    let mut entry = id.to_le_bytes().to_vec();
+    for value in vector {
+        entry.extend(value.to_le_bytes());
+    }
+    entry
+}
+
+impl VectorStore {
+    // Load the stored vectors, and embed the text of indexed screens that have none:
+    // screens added while semantic search was disabled, or all of them when the embedder
+    // changed. Vectors of screens no longer in the index are dropped.
+    pub fn open(embedder: Arc<dyn Embedder>, index: &Index, schema: &Schema) -> io::Result<Self> {
+        let dimensions = embedder.dimensions();
+        let mut ann = AnnIndex::new(dimensions);
+        let entries = stored_entries()?;
+        let same_embedder = entries.first() == Some(&header(&*embedder));
+        if same_embedder {
+            for entry in &entries[1..] {
+                if entry.len() != 8 + 4 * dimensions {
+                    error!("Invalid stored vector");
+                    continue;
+                }
+                let id = u64::from_le_bytes(entry[..8].try_into().unwrap());
+                let vector = entry[8..]
+                    .chunks(4)
+                    .map(|bytes| f32::from_le_bytes(bytes.try_into().unwrap()))
+                    .collect();
+                ann.insert(id, vector);
+            }
+        } else if !entries.is_empty() {
+            info!(
+                embedder = %embedder.name(),
+                "The embedder changed, embedding all screens again"
+            );
+        }
+
+        let ids = indexed_ids(index, schema);
+        let removed: Vec<u64> = ann.ids().filter(|id| !ids.contains(id)).collect();
+        for id in &removed {
+            ann.remove(*id);
+        }
+        let missing = embed_missing(&*embedder, &mut ann, index, schema, &ids);
+        if missing > 0 {
+            info!(screens = missing, "Embedded screens for semantic search");
+        }
+
+        if !same_embedder || !removed.is_empty() || missing > 0 {
+            rewrite(&*embedder, &ann)?;
+        }
+        Ok(VectorStore {
+            embedder,
+            index: RwLock::new(ann),
+            file: Mutex::new(OpenOptions::new().append(true).open(VECTORS_PATH)?),
+        })
+    }
+
+    // Embed and store the text of a screen. Texts without anything to embed are skipped.
+    pub fn add(&self, id: u64, text: &str) -> io::Result<()> {
+        let vector = match self.embedder.embed(text) {
+            Some(vector) => vector,
+            None => return Ok(()),
+        };
+        write_entry(&mut self.file.lock().unwrap(), &vector_entry(id, &vector))?;
+        self.index.write().unwrap().insert(id, vector);
+        Ok(())
+    }
+
+    // Forget deleted screens. The file is rewritten without their vectors.
+    pub fn remove(&self, ids: &[u64]) -> io::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        let mut index = self.index.write().unwrap();
+        for id in ids {
+            index.remove(*id);
+        }
+        rewrite(&*self.embedder, &index)?;
+        *file = OpenOptions::new().append(true).open(VECTORS_PATH)?;
+        Ok(())
+    }
+
+    // Screens whose text is closest in meaning to the query, most similar first
+    pub fn search(&self, query: &str, limit: usize) -> Vec<(u64, f32)> {
+        match self.embedder.embed(query) {
+            Some(vector) => self.index.read().unwrap().search(&vector, limit),
+            None => vec![],
+        }
+    }
+}
+
+// Replace the file with one holding exactly the vectors in memory
+fn rewrite(embedder: &dyn Embedder, ann: &AnnIndex) -> io::Result<()> {
+    let tmp_path = format!("{}.tmp", VECTORS_PATH);
+    let mut file = File::create(&tmp_path)?;
+    write_entry(&mut file, &header(embedder))?;
+    for id in ann.ids() {
+        write_entry(&mut file, &vector_entry(id, ann.vector(id).unwrap()))?;
+    }
+    file.sync_all()?;
+    std::fs::rename(&tmp_path, VECTORS_PATH)
+}
+
+// Embed the indexed screens that have no vector yet. Returns how many were embedded.
+fn embed_missing(
+    embedder: &dyn Embedder,
+    ann: &mut AnnIndex,
+    index: &Index,
+    schema: &Schema,
+    ids: &HashSet<u64>,
+) -> usize {
+    if ids.iter().all(|id| ann.contains(*id)) {
+        return 0;
+    }
+    let id_field = schema.get_field("id").unwrap();
+    let text_field = schema.get_field("text").unwrap();
+    let searcher = index.reader().unwrap().searcher();
+    let mut count = 0;
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        let id_values = segment_reader.fast_fields().u64(id_field).unwrap();
+        for doc in segment_reader.doc_ids_alive() {
+            let id = id_values.get_val(doc as u64);
+            if ann.contains(id) {
+                continue;
+            }
+            let document = searcher
+                .doc(DocAddress::new(segment_ord as u32, doc))
+                .unwrap();
+            let text = document
+                .get_first(text_field)
+                .and_then(|value| value.as_text())
+                .unwrap_or("");
+            if let Some(vector) = embedder.embed(text) {
+                ann.insert(id, vector);
+                count += 1;
+            }
+        }
+    }
+    count
+}
@@ -0,0 +1,95 @@
+use futures::future::BoxFuture;
+use hyper::header::{ACCESS_CONTROL_REQUEST_METHOD, CONTENT_TYPE};
+use hyper::{Body, Method, Request, Response};
+use std::path::Path;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tonic::body::BoxBody;
+use tonic::codegen::{Service, StdError};
+use tower::Layer;
+use tower_http::services::{ServeDir, ServeFile};
+
+use crate::rest::RestApi;
+
+// Serves the built web interface (web/dist) next to the gRPC API, so the interface and
+// the API share one port. gRPC and gRPC-Web requests, and CORS preflights for them, go to
+// the API, and /api/ to the JSON API; everything else is a static file. Unknown paths get
+// index.html.
+#[derive(Clone)]
+pub struct WebLayer {
+    files: ServeDir<ServeFile>,
+    rest: Arc<RestApi>,
+}
+
+impl WebLayer {
+    pub fn new(dir: &Path, rest: RestApi) -> Self {
+        WebLayer {
+            files: ServeDir::new(dir).fallback(ServeFile::new(dir.join("index.html"))),
+            rest: Arc::new(rest),
+        }
+    }
+}
+
+impl<S> Layer<S> for WebLayer {
+    type Service = WebService<S>;
+
+    fn layer(&self, api: S) -> Self::Service {
+        WebService {
+            api,
+            files: self.files.clone(),
+            rest: self.rest.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct WebService<S> {
+    api: S,
+    files: ServeDir<ServeFile>,
+    rest: Arc<RestApi>,
+}
+
+fn is_api_request(request: &Request<Body>) -> bool {
+    let grpc = request
+        .headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map_or(false, |value| value.starts_with("application/grpc"));
+    let preflight = request.method() == Method::OPTIONS
+        && request
+            .headers()
+            .contains_key(ACCESS_CONTROL_REQUEST_METHOD);
+    grpc || preflight
+}
+
+impl<S> Service<Request<Body>> for WebService<S>
+where
+    S: Service<Request<Body>, Response = Response<BoxBody>>,
+    S::Error: Into<StdError>,
+    S::Future: Send + 'static,
+{
+    type Response = Response<BoxBody>;
+    type Error = StdError;
+    type Future = BoxFuture<'static, Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.api.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, request: Request<Body>) -> Self::Future {
+        if is_api_request(&request) {
+            let response = self.api.call(request);
+            return Box::pin(async move { response.await.map_err(Into::into) });
+        }
+        if request.uri().path().starts_with("/api/") {
+            let rest = self.rest.clone();
+            return Box::pin(async move { Ok(rest.handle(request).await.map(tonic::body::boxed)) });
+        }
+        // Serving files is always ready
+        let response = self.files.call(request);
+        Box::pin(async move {
+            let response = response.await?;
+            Ok(response.map(tonic::body::boxed))
+        })
+    }
+}
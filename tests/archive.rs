@@ -0,0 +1,79 @@
+mod common;
+
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use common::{enter_temp_dir, jpeg, test_config};
+use pms::api::{ScreenRecord, WordBox};
+use pms::archive;
+use pms::index::{indexed_ids, make_schema};
+use pms::storage::{
+    datetime_to_screen_path, read_file, read_sidecar, screen_uid, to_timestamp, write_sidecar,
+};
+
+fn datetime(seconds: i64, nanos: u32) -> chrono::NaiveDateTime {
+    chrono::NaiveDateTime::from_timestamp_opt(seconds, nanos).unwrap()
+}
+
+// Store a screenshot and its OCR output the way the server does
+fn store_screen(datetime: chrono::NaiveDateTime, screen_id: u32, image: &[u8], text: &str) {
+    let (dir, fname) = datetime_to_screen_path(datetime, screen_id);
+    create_dir_all(&dir).unwrap();
+    let path = PathBuf::from(dir + &fname);
+    std::fs::write(&path, image).unwrap();
+    let record = ScreenRecord {
+        time: Some(to_timestamp(datetime)),
+        screen_id,
+        text: text.to_string(),
+        words: vec![WordBox {
+            text: text.to_string(),
+            left: 1,
+            top: 2,
+            width: 3,
+            height: 4,
+            confidence: 90.0,
+        }],
+        dhash: vec![],
+    };
+    write_sidecar(&path, &record).unwrap();
+}
+
+#[test]
+fn export_import_round_trip() {
+    let archive_dir = tempfile::tempdir().unwrap();
+    let archive_path = archive_dir.path().join("january.tar");
+    let screens = [
+        (datetime(1_675_166_400, 0), 1, jpeg(0), "invoice"),
+        (datetime(1_675_166_401, 500), 2, jpeg(1), "receipt"),
+        // Outside the exported range
+        (datetime(1_677_628_800, 0), 1, jpeg(2), "march"),
+    ];
+
+    let _source = enter_temp_dir();
+    for (datetime, screen_id, image, text) in &screens {
+        store_screen(*datetime, *screen_id, image, text);
+    }
+    let start = archive::parse_range_bound("2023-01-01", false).unwrap();
+    let end = archive::parse_range_bound("2023-01-31", true).unwrap();
+    assert_eq!(archive::export(start, end, &archive_path).unwrap(), 2);
+
+    let _target = enter_temp_dir();
+    let config = test_config("");
+    assert_eq!(archive::import(&config, &archive_path).unwrap(), (2, 0));
+    for (datetime, screen_id, image, text) in &screens[..2] {
+        let (dir, fname) = datetime_to_screen_path(*datetime, *screen_id);
+        let path = PathBuf::from(dir + &fname);
+        assert_eq!(&read_file(&path).unwrap(), image);
+        let record = read_sidecar(&path).unwrap();
+        assert_eq!(record.text, *text);
+        assert_eq!(record.words.len(), 1);
+        assert!(!record.dhash.is_empty());
+    }
+    let (schema, index) = make_schema();
+    let ids = indexed_ids(&index, &schema);
+    assert_eq!(ids.len(), 2);
+    assert!(ids.contains(&screen_uid(screens[1].0, 2)));
+
+    // Importing again changes nothing
+    assert_eq!(archive::import(&config, &archive_path).unwrap(), (0, 2));
+}
@@ -0,0 +1,49 @@
+// Each test binary uses only some of these helpers
+#![allow(dead_code)]
+
+use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Rgb};
+use std::io::Cursor;
+
+use pms::config::Config;
+
+// The server keeps its data in the working directory, so each test binary runs in a
+// temporary one. Tests sharing a binary must not run this concurrently.
+pub fn enter_temp_dir() -> tempfile::TempDir {
+    let dir = tempfile::tempdir().unwrap();
+    std::env::set_current_dir(dir.path()).unwrap();
+    dir
+}
+
+// A configuration with the fixture OCR engine and no tokens, independent of the
+// environment the tests run in
+pub fn test_config(text: &str) -> Config {
+    Config {
+        ocr_engine: "fixture".to_string(),
+        ocr_fixture_text: text.to_string(),
+        upload_token: None,
+        search_token: None,
+        delete_token: None,
+        ..Config::from_env()
+    }
+}
+
+// A JPEG whose image hash differs for each pattern
+pub fn jpeg(pattern: u8) -> Vec<u8> {
+    let image = ImageBuffer::from_fn(64, 64, |x, y| {
+        let value = match pattern % 3 {
+            0 => x * 4,
+            1 => 255 - x * 4,
+            _ => (x / 8 + y / 8) % 2 * 255,
+        };
+        Rgb([value as u8; 3])
+    });
+    let mut data = Cursor::new(vec![]);
+    DynamicImage::ImageRgb8(image)
+        .write_to(&mut data, ImageOutputFormat::Jpeg(90))
+        .unwrap();
+    data.into_inner()
+}
+
+pub fn time(seconds: i64, nanos: i32) -> prost_types::Timestamp {
+    prost_types::Timestamp { seconds, nanos }
+}
@@ -0,0 +1,72 @@
+mod common;
+
+use std::fs::{create_dir_all, OpenOptions};
+
+use common::{enter_temp_dir, jpeg, test_config, time};
+use pms::api::ScreenRecord;
+use pms::index::{indexed_ids, make_schema};
+use pms::journal::{self, Journal, JOURNAL_PATH};
+use pms::storage::{datetime_to_screen_path, from_timestamp, screen_uid};
+
+fn record(seconds: i64, screen_id: u32, stored: bool) -> ScreenRecord {
+    let datetime = chrono::NaiveDateTime::from_timestamp_opt(seconds, 0).unwrap();
+    if stored {
+        let (dir, fname) = datetime_to_screen_path(datetime, screen_id);
+        create_dir_all(&dir).unwrap();
+        std::fs::write(dir + &fname, jpeg(screen_id as u8)).unwrap();
+    }
+    ScreenRecord {
+        time: Some(time(seconds, 0)),
+        screen_id,
+        text: "invoice".to_string(),
+        words: vec![],
+        dhash: vec![],
+    }
+}
+
+fn uid(record: &ScreenRecord) -> u64 {
+    let datetime = from_timestamp(record.time.as_ref().unwrap()).unwrap();
+    screen_uid(datetime, record.screen_id)
+}
+
+#[test]
+fn replay_skips_truncated_tail() {
+    let _dir = enter_temp_dir();
+    let config = test_config("");
+    let stored = record(1_675_166_400, 1, true);
+    // Deleted before the journal was replayed
+    let deleted = record(1_675_166_401, 1, false);
+    let truncated = record(1_675_166_402, 2, true);
+    {
+        let journal = Journal::open(JOURNAL_PATH).unwrap();
+        for record in [&stored, &deleted, &truncated] {
+            journal.append(record).unwrap();
+        }
+    }
+    // A crash while the last entry was written
+    let len = std::fs::metadata(JOURNAL_PATH).unwrap().len();
+    OpenOptions::new()
+        .write(true)
+        .open(JOURNAL_PATH)
+        .unwrap()
+        .set_len(len - 3)
+        .unwrap();
+
+    let (schema, index) = make_schema();
+    let mut writer = index.writer(50_000_000).unwrap();
+    let replayed = journal::replay(&config, &schema, &mut writer, JOURNAL_PATH).unwrap();
+    assert_eq!(replayed, 1);
+    assert_eq!(indexed_ids(&index, &schema), [uid(&stored)].into());
+    assert_eq!(std::fs::metadata(JOURNAL_PATH).unwrap().len(), 0);
+
+    // Replaying a screen that was committed already replaces it
+    Journal::open(JOURNAL_PATH)
+        .unwrap()
+        .append(&stored)
+        .unwrap();
+    assert_eq!(
+        journal::replay(&config, &schema, &mut writer, JOURNAL_PATH).unwrap(),
+        1
+    );
+    assert_eq!(index.reader().unwrap().searcher().num_docs(), 1);
+}
@@ -0,0 +1,112 @@
+mod common;
+
+use std::collections::HashMap;
+use std::fs::create_dir_all;
+use std::path::PathBuf;
+
+use common::{enter_temp_dir, jpeg, test_config};
+use pms::dhash::image_dhash;
+use pms::index::{doc_datetime, make_schema, INDEX_PATH};
+use pms::migrate::prepare_index;
+use pms::ocr::engine_from_config;
+use pms::storage::{datetime_to_screen_path, read_sidecar, screen_uid};
+use tantivy::schema::{SchemaBuilder, STORED, TEXT};
+use tantivy::{doc, DocAddress, Index};
+
+fn store_image(datetime: chrono::NaiveDateTime, screen_id: u32, image: &[u8]) -> PathBuf {
+    let (dir, fname) = datetime_to_screen_path(datetime, screen_id);
+    create_dir_all(&dir).unwrap();
+    let path = PathBuf::from(dir + &fname);
+    std::fs::write(&path, image).unwrap();
+    path
+}
+
+// An index as the first versions of pms-server created it: no ids, times stored to the
+// second, no word boxes or image hashes, and no schema_version file
+fn create_v1_index(screens: &[(chrono::NaiveDateTime, u32, &str)]) {
+    let mut schema_builder = SchemaBuilder::default();
+    let date = schema_builder.add_date_field("date", STORED);
+    let text = schema_builder.add_text_field("text", TEXT | STORED);
+    let screen_id = schema_builder.add_u64_field("screen_id", STORED);
+    let schema = schema_builder.build();
+
+    create_dir_all(INDEX_PATH).unwrap();
+    let index = Index::create_in_dir(INDEX_PATH, schema).unwrap();
+    let mut writer = index.writer(50_000_000).unwrap();
+    for (datetime, id, content) in screens {
+        writer
+            .add_document(doc!(
+                date => tantivy::DateTime::from_timestamp_secs(datetime.timestamp()),
+                text => *content,
+                screen_id => *id as u64,
+            ))
+            .unwrap();
+    }
+    writer.commit().unwrap();
+}
+
+#[tokio::test]
+async fn migrates_version_1_index() {
+    let _dir = enter_temp_dir();
+    let datetime = |seconds| chrono::NaiveDateTime::from_timestamp_opt(seconds, 0).unwrap();
+    let indexed = [
+        (datetime(1_675_166_400), 1, "invoice"),
+        (datetime(1_675_166_401), 2, "receipt"),
+    ];
+    let images = [jpeg(0), jpeg(1)];
+    let mut paths = vec![];
+    for ((datetime, screen_id, _), image) in indexed.iter().zip(&images) {
+        paths.push(store_image(*datetime, *screen_id, image));
+    }
+    // Stored, but missing from the old index
+    let unindexed = (datetime(1_675_166_402), 1);
+    store_image(unindexed.0, unindexed.1, &jpeg(2));
+    create_v1_index(&indexed);
+
+    let config = test_config("fixture text");
+    prepare_index(&config, engine_from_config(&config))
+        .await
+        .unwrap();
+
+    assert_eq!(std::fs::read_to_string("schema_version").unwrap(), "3\n");
+    assert!(!PathBuf::from("index.migrating").exists());
+
+    let (schema, index) = make_schema();
+    let searcher = index.reader().unwrap().searcher();
+    let field = |name| schema.get_field(name).unwrap();
+    let mut docs = HashMap::new();
+    for (segment_ord, segment_reader) in searcher.segment_readers().iter().enumerate() {
+        for doc_id in segment_reader.doc_ids_alive() {
+            let doc = searcher
+                .doc(DocAddress::new(segment_ord as u32, doc_id))
+                .unwrap();
+            let id = doc.get_first(field("id")).unwrap().as_u64().unwrap();
+            docs.insert(id, doc);
+        }
+    }
+    assert_eq!(docs.len(), 3);
+
+    for (((datetime, screen_id, text), image), path) in indexed.iter().zip(&images).zip(&paths) {
+        let doc = &docs[&screen_uid(*datetime, *screen_id)];
+        assert_eq!(doc_datetime(&schema, doc), *datetime);
+        assert_eq!(doc.get_first(field("text")).unwrap().as_text(), Some(*text));
+        let dhash = doc.get_first(field("dhash")).unwrap().as_bytes().unwrap();
+        assert_eq!(Some(dhash.to_vec()), image_dhash(image));
+        // The migrated OCR output is kept next to the image
+        let record = read_sidecar(path).unwrap();
+        assert_eq!(record.text, *text);
+        assert_eq!(record.dhash, dhash);
+    }
+    let doc = &docs[&screen_uid(unindexed.0, unindexed.1)];
+    assert_eq!(
+        doc.get_first(field("text")).unwrap().as_text(),
+        Some("fixture text")
+    );
+    assert_eq!(
+        doc.get_first(field("dhash"))
+            .unwrap()
+            .as_bytes()
+            .map(<[u8]>::to_vec),
+        image_dhash(&jpeg(2))
+    );
+}
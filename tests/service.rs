@@ -0,0 +1,171 @@
+mod common;
+
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::sync::RwLock;
+use tokio_stream::wrappers::TcpListenerStream;
+use tonic::codegen::InterceptedService;
+use tonic::transport::{Channel, Server};
+use tonic::Code;
+
+use common::{enter_temp_dir, jpeg, test_config, time};
+use pms::api::pms_service_client::PmsServiceClient;
+use pms::api::pms_service_server::PmsServiceServer;
+use pms::api::search_response::Screen;
+use pms::api::{
+    DeleteScreensRequest, GetScreenRequest, ListScreensRequest, SearchRequest, UploadScreenRequest,
+};
+use pms::auth;
+use pms::index::make_schema;
+use pms::journal::{Journal, JOURNAL_PATH};
+use pms::migrate::prepare_index;
+use pms::ocr::engine_from_config;
+use pms::service::ImplPMSService;
+
+async fn search(client: &mut PmsServiceClient<Channel>, query: &str) -> Vec<Screen> {
+    client
+        .search_screens(SearchRequest {
+            query: query.to_string(),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .screens
+}
+
+// Search until the given number of screens is found. The index reader picks up commits
+// asynchronously.
+async fn search_until(
+    client: &mut PmsServiceClient<Channel>,
+    query: &str,
+    count: usize,
+) -> Vec<Screen> {
+    for _ in 0..100 {
+        let screens = search(client, query).await;
+        if screens.len() == count {
+            return screens;
+        }
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    panic!("Search for {:?} did not find {} screens", query, count);
+}
+
+#[tokio::test]
+async fn upload_search_list_get_delete() {
+    let _dir = enter_temp_dir();
+    let config = test_config("invoice from acme");
+    prepare_index(&config, engine_from_config(&config))
+        .await
+        .unwrap();
+    let (schema, index) = make_schema();
+    let writer = Arc::new(RwLock::new(index.writer(50_000_000).unwrap()));
+    let journal = Arc::new(Journal::open(JOURNAL_PATH).unwrap());
+    let service = ImplPMSService::new(
+        config.clone(),
+        engine_from_config(&config),
+        schema,
+        index,
+        writer.clone(),
+        journal.clone(),
+        None,
+    );
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(InterceptedService::new(
+                PmsServiceServer::new(service),
+                auth::interceptor(&config),
+            ))
+            .serve_with_incoming(TcpListenerStream::new(listener)),
+    );
+    let mut client = PmsServiceClient::connect(format!("http://{}", addr))
+        .await
+        .unwrap();
+
+    // 2023-01-31 12:00:00, on two screens
+    let uploads = [
+        (time(1_675_166_400, 0), 1, jpeg(0)),
+        (time(1_675_166_401, 500), 2, jpeg(1)),
+        // The same image again is skipped
+        (time(1_675_166_402, 0), 1, jpeg(0)),
+    ];
+    for (time, screen_id, image) in &uploads {
+        let request = UploadScreenRequest {
+            time: Some(time.clone()),
+            screen_id: *screen_id,
+            image: image.clone(),
+        };
+        let ack = client
+            .upload_screen(futures::stream::iter([request]))
+            .await
+            .unwrap()
+            .into_inner();
+        assert!(ack.success);
+    }
+    journal.commit(&writer).await.unwrap();
+
+    let screens = search_until(&mut client, "invoice", 2).await;
+    assert!(screens
+        .iter()
+        .all(|screen| screen.text == "invoice from acme"));
+    assert!(search(&mut client, "receipt").await.is_empty());
+
+    let listed = client
+        .list_screens(ListScreensRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    let times: Vec<_> = listed.screens.iter().map(|s| s.time.clone()).collect();
+    assert_eq!(
+        times,
+        [Some(uploads[0].0.clone()), Some(uploads[1].0.clone())]
+    );
+    assert!(listed.next_page_token.is_empty());
+    let second_screen = client
+        .list_screens(ListScreensRequest {
+            screen_id: Some(2),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(second_screen.screens.len(), 1);
+    let first = &listed.screens[0];
+    assert_eq!(first.image, uploads[0].2);
+
+    let screen = client
+        .get_screen(GetScreenRequest { id: first.id })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(screen.screen_id, 1);
+    assert_eq!(screen.image, uploads[0].2);
+    assert_eq!(screen.words.len(), 3);
+
+    let deleted = client
+        .delete_screens(DeleteScreensRequest {
+            ids: vec![listed.screens[1].id],
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(deleted.screens.len(), 1);
+    assert_eq!(deleted.screens[0].time, Some(uploads[1].0.clone()));
+    let remaining = search_until(&mut client, "invoice", 1).await;
+    assert_eq!(remaining[0].id, first.id);
+    let status = client
+        .get_screen(GetScreenRequest {
+            id: listed.screens[1].id,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), Code::NotFound);
+    assert_eq!(
+        std::fs::read_dir("screenshots/2023/1/31").unwrap().count(),
+        2
+    );
+}
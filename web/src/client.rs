@@ -0,0 +1,66 @@
+use tonic::service::{interceptor::InterceptedService, Interceptor};
+use tonic_web_wasm_client::Client;
+
+use crate::api::pms_service_client::PmsServiceClient;
+
+// Client sending the search token, if one is set
+pub type AuthClient = PmsServiceClient<InterceptedService<Client, Authorization>>;
+
+#[derive(Clone)]
+pub struct Authorization {
+    token: String,
+}
+
+impl Interceptor for Authorization {
+    fn call(
+        &mut self,
+        mut request: tonic::Request<()>,
+    ) -> Result<tonic::Request<()>, tonic::Status> {
+        if !self.token.is_empty() {
+            let value = format!("Bearer {}", self.token)
+                .parse()
+                .map_err(|_| tonic::Status::invalid_argument("Invalid token"))?;
+            request.metadata_mut().insert("authorization", value);
+        }
+        Ok(request)
+    }
+}
+
+// The search token is kept in local storage, so it only has to be entered once
+const TOKEN_KEY: &str = "pms-search-token";
+
+fn local_storage() -> Option<web_sys::Storage> {
+    web_sys::window()?.local_storage().ok()?
+}
+
+pub fn load_token() -> String {
+    local_storage()
+        .and_then(|storage| storage.get_item(TOKEN_KEY).ok()?)
+        .unwrap_or_default()
+}
+
+pub fn save_token(token: &str) {
+    if let Some(storage) = local_storage() {
+        let _ = storage.set_item(TOKEN_KEY, token);
+    }
+}
+
+// The API is served from the same origin as the interface. PMS_SERVER at build time
+// overrides this, e.g. for `trunk serve` during development.
+fn server_url() -> String {
+    match option_env!("PMS_SERVER") {
+        Some(url) => url.to_string(),
+        None => web_sys::window()
+            .and_then(|window| window.location().origin().ok())
+            .unwrap_or_default(),
+    }
+}
+
+pub fn client(token: &str) -> AuthClient {
+    PmsServiceClient::with_interceptor(
+        Client::new(server_url()),
+        Authorization {
+            token: token.to_string(),
+        },
+    )
+}
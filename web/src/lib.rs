@@ -1,5 +1,7 @@
+pub mod client;
 pub mod results;
 pub mod search;
+pub mod status;
 pub mod text_input;
 
 pub mod api {
@@ -1,15 +1,45 @@
-use web::api::SearchResponse;
+use web::api::find_similar_screens_request::Target;
+use web::api::{FindSimilarScreensRequest, SearchResponse};
+use web::client::{client, load_token};
 use web::results::ResultsComponent;
 use web::search::SearchComponent;
+use web::status::StatusComponent;
 use yew::prelude::*;
 
+#[derive(PartialEq)]
+enum Page {
+    Results,
+    Status,
+}
+
 struct App {
     query: String,
     search_results: Option<SearchResponse>,
+    page: Page,
 }
 
 enum AppMessage {
     SearchResponse(String, Option<SearchResponse>),
+    FindSimilar(u64),
+    ShowStatus,
+}
+
+// The screens that look most like a screen, as search results
+async fn find_similar(id: u64) -> Option<SearchResponse> {
+    let response = client(&load_token())
+        .find_similar_screens(FindSimilarScreensRequest {
+            target: Some(Target::Id(id)),
+            ..Default::default()
+        })
+        .await
+        .ok()?;
+    let screens = response
+        .into_inner()
+        .matches
+        .into_iter()
+        .filter_map(|m| m.screen)
+        .collect();
+    Some(SearchResponse { screens })
 }
 
 impl Component for App {
@@ -20,14 +50,26 @@ impl Component for App {
         Self {
             query: "".to_string(),
             search_results: None,
+            page: Page::Results,
         }
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             AppMessage::SearchResponse(query, response) => {
                 self.query = query;
                 self.search_results = response;
+                self.page = Page::Results;
+                true
+            }
+            AppMessage::FindSimilar(id) => {
+                ctx.link().send_future(async move {
+                    AppMessage::SearchResponse(String::new(), find_similar(id).await)
+                });
+                false
+            }
+            AppMessage::ShowStatus => {
+                self.page = Page::Status;
                 true
             }
         }
@@ -37,6 +79,7 @@ impl Component for App {
         let onresult = ctx
             .link()
             .callback(|(q, r)| AppMessage::SearchResponse(q, r));
+        let onsimilar = ctx.link().callback(AppMessage::FindSimilar);
         html! {
             <>
             <nav class="navbar" role="navigation" aria-label="main navigation">
@@ -45,11 +88,21 @@ impl Component for App {
                     <div class="navbar-item">
                         <SearchComponent {onresult} />
                     </div>
+                    <div class="navbar-item">
+                        <button class="button" onclick={ctx.link().callback(|_| AppMessage::ShowStatus)}>{ "Status" }</button>
+                    </div>
                 </div>
 
             </nav>
             <div class="container">
-                <ResultsComponent results={self.search_results.clone()} query={self.query.clone()} />
+                {
+                    match self.page {
+                        Page::Results => html! {
+                            <ResultsComponent results={self.search_results.clone()} query={self.query.clone()} {onsimilar} />
+                        },
+                        Page::Status => html! { <StatusComponent /> },
+                    }
+                }
             </div>
             <footer>
             </footer>
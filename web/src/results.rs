@@ -11,6 +11,8 @@ pub struct ResultsComponent {}
 pub struct ResultsProps {
     pub query: String,
     pub results: Option<SearchResponse>,
+    // Called with the id of a screen when its "More like this" button is clicked
+    pub onsimilar: Callback<u64>,
 }
 
 impl Component for ResultsComponent {
@@ -36,6 +38,8 @@ impl Component for ResultsComponent {
                         { for results.screens.iter().map(|result| {
                             let image_str = "data:image/jpeg;base64,".to_string() + &base64::encode(&result.image);
                             let (before, mid, after) = make_snippet(&result.text, &ctx.props().query, 20);
+                            let id = result.id;
+                            let onsimilar = ctx.props().onsimilar.reform(move |_| id);
                             html! {
                                 <div class="card">
                                     <div class="card-header">
@@ -50,6 +54,7 @@ impl Component for ResultsComponent {
                                         <div class="card-footer-item">
                                             <time datetime={ result.time.clone().unwrap().to_string() }>{ result.time.clone().unwrap().to_string() }</time>
                                         </div>
+                                        <a class="card-footer-item" onclick={onsimilar}>{ "More like this" }</a>
                                     </div>
                                 </div>
                             }
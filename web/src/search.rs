@@ -1,13 +1,12 @@
 use std::error::Error;
 use std::fmt::{self, Debug, Display, Formatter};
-use tonic_web_wasm_client::Client;
 use wasm_bindgen::JsValue;
 use web_sys::console;
 use yew::prelude::*;
 
+use crate::client::{client, load_token, save_token, AuthClient};
 use crate::text_input::TextInput;
 
-use crate::api::pms_service_client::PmsServiceClient;
 use crate::api::{SearchRequest, SearchResponse};
 
 // Something wrong has occurred while searching
@@ -36,22 +35,15 @@ impl From<tonic::Status> for SearchError {
 }
 
 async fn search_screens(
-    query_client: &mut PmsServiceClient<Client>,
+    query_client: &mut AuthClient,
     query: &str,
 ) -> Result<SearchResponse, SearchError> {
-    let start_time = prost_types::Timestamp {
-        seconds: 0 as i64,
-        nanos: 0,
-    };
-    let end_time = prost_types::Timestamp {
-        seconds: 0 as i64,
-        nanos: 0,
-    };
     let response = query_client
         .search_screens(SearchRequest {
             query: query.to_string(),
-            start_time: Some(start_time),
-            end_time: Some(end_time),
+            start_time: None,
+            end_time: None,
+            ..Default::default()
         })
         .await?;
     return Ok(response.into_inner());
@@ -60,6 +52,7 @@ async fn search_screens(
 pub enum SearchMsg {
     Search(),
     SetQuery(String),
+    SetToken(String),
     Response(Option<SearchResponse>),
 }
 
@@ -71,6 +64,7 @@ pub enum SearchState {
 pub struct SearchComponent {
     search_state: SearchState,
     query: String,
+    token: String,
 }
 
 #[derive(Clone, PartialEq, Properties)]
@@ -85,15 +79,14 @@ impl Component for SearchComponent {
         Self {
             search_state: SearchState::NotSearching,
             query: "".to_string(),
+            token: load_token(),
         }
     }
 
     fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             SearchMsg::Search() => {
-                let mut query_client = PmsServiceClient::new(tonic_web_wasm_client::Client::new(
-                    "http://localhost:50001".to_string(),
-                ));
+                let mut query_client = client(&self.token);
                 let query_cp = self.query.clone();
                 ctx.link().send_future(async move {
                     console::log_1(&"Sending request".into());
@@ -116,17 +109,26 @@ impl Component for SearchComponent {
                 self.query = query;
                 false
             }
+            SearchMsg::SetToken(token) => {
+                save_token(&token);
+                self.token = token;
+                false
+            }
         }
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let onchange = ctx.link().callback(SearchMsg::SetQuery);
+        let ontokenchange = ctx.link().callback(SearchMsg::SetToken);
 
         html! {
                 <form class="navbar-item" action="javascript:void(0);">
                 <div class="navbar-item">
                     <TextInput {onchange} placeholder="Search query" value={self.query.clone()} />
                 </div>
+                <div class="navbar-item">
+                    <TextInput onchange={ontokenchange} placeholder="Token" input_type="password" value={self.token.clone()} />
+                </div>
                 <div class="navbar-item">
                 {
                     match &self.search_state {
@@ -0,0 +1,120 @@
+// Status page showing the statistics of the archive
+use yew::prelude::*;
+
+use crate::api::{GetStatsRequest, GetStatsResponse};
+use crate::client::{client, load_token};
+
+pub enum StatusMsg {
+    Refresh(),
+    Response(Result<GetStatsResponse, String>),
+}
+
+pub struct StatusComponent {
+    stats: Option<Result<GetStatsResponse, String>>,
+}
+
+impl Component for StatusComponent {
+    type Message = StatusMsg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        ctx.link().send_message(StatusMsg::Refresh());
+        Self { stats: None }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            StatusMsg::Refresh() => {
+                let mut stats_client = client(&load_token());
+                ctx.link().send_future(async move {
+                    let response = stats_client.get_stats(GetStatsRequest {}).await;
+                    StatusMsg::Response(
+                        response
+                            .map(|response| response.into_inner())
+                            .map_err(|status| status.message().to_string()),
+                    )
+                });
+                false
+            }
+            StatusMsg::Response(stats) => {
+                self.stats = Some(stats);
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let stats = match &self.stats {
+            None => return html! { <p>{ "Loading..." }</p> },
+            Some(Err(message)) => {
+                return html! { <div class="notification is-danger">{ message }</div> }
+            }
+            Some(Ok(stats)) => stats,
+        };
+        let dedupe_rate = if stats.uploads == 0 {
+            0.0
+        } else {
+            stats.duplicates as f64 / stats.uploads as f64 * 100.0
+        };
+        html! {
+            <div class="section">
+                <button class="button" onclick={ctx.link().callback(|_| StatusMsg::Refresh())}>{ "Refresh" }</button>
+                <table class="table">
+                    <tbody>
+                        <tr><th>{ "Screens" }</th><td>{ stats.total_screens }</td></tr>
+                        <tr><th>{ "Images on disk" }</th><td>{ format_bytes(stats.image_bytes) }</td></tr>
+                        <tr><th>{ "Index on disk" }</th><td>{ format_bytes(stats.index_bytes) }</td></tr>
+                        <tr><th>{ "Index segments" }</th><td>{ stats.segment_count }</td></tr>
+                        <tr><th>{ "OCR queue" }</th><td>{ stats.ocr_queue_depth }</td></tr>
+                        <tr><th>{ "Uploads since start" }</th><td>{ stats.uploads }</td></tr>
+                        <tr><th>{ "Skipped as duplicates" }</th><td>{ format!("{} ({:.1}%)", stats.duplicates, dedupe_rate) }</td></tr>
+                    </tbody>
+                </table>
+
+                <h3 class="title is-5">{ "Last upload per client" }</h3>
+                <table class="table">
+                    <thead><tr><th>{ "Client" }</th><th>{ "Time" }</th></tr></thead>
+                    <tbody>
+                    { for stats.last_uploads.iter().map(|upload| html! {
+                        <tr>
+                            <td>{ &upload.client }</td>
+                            <td>{ upload.time.clone().map(|time| time.to_string()).unwrap_or_default() }</td>
+                        </tr>
+                    }) }
+                    </tbody>
+                </table>
+
+                <h3 class="title is-5">{ "Screens per display" }</h3>
+                <table class="table">
+                    <thead><tr><th>{ "Display" }</th><th>{ "Screens" }</th></tr></thead>
+                    <tbody>
+                    { for stats.screens.iter().map(|screen| html! {
+                        <tr><td>{ screen.screen_id }</td><td>{ screen.count }</td></tr>
+                    }) }
+                    </tbody>
+                </table>
+
+                <h3 class="title is-5">{ "Screens per day" }</h3>
+                <table class="table">
+                    <thead><tr><th>{ "Day" }</th><th>{ "Screens" }</th></tr></thead>
+                    <tbody>
+                    { for stats.days.iter().rev().map(|day| html! {
+                        <tr><td>{ &day.day }</td><td>{ day.count }</td></tr>
+                    }) }
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut value = bytes as f64;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
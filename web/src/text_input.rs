@@ -6,6 +6,8 @@ use yew::prelude::*;
 pub struct Props {
     pub value: String,
     pub placeholder: String,
+    #[prop_or_else(|| "search".to_string())]
+    pub input_type: String,
     pub onchange: Callback<String>,
 }
 
@@ -22,6 +24,7 @@ pub fn text_input(props: &Props) -> Html {
     let Props {
         value,
         placeholder,
+        input_type,
         onchange,
     } = props.clone();
 
@@ -30,6 +33,6 @@ pub fn text_input(props: &Props) -> Html {
     });
 
     html! {
-        <input class="input" type="search" {value} {oninput} {placeholder} />
+        <input class="input" type={input_type} {value} {oninput} {placeholder} />
     }
 }